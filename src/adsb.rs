@@ -0,0 +1,683 @@
+// Mode S extended squitter (DF17/DF18) decoder, see "The 1090 Megahertz Riddle" by Junzi Sun.
+
+use std::collections::HashMap;
+use std::f64::consts::PI;
+use std::fmt;
+use std::time::{Duration, Instant};
+
+// Number of latitude zones between equator and pole for CPR
+const CPR_NZ: f64 = 15.0;
+const CPR_MAX: f64 = 131072.0; // 2^17
+
+// Even and odd frames older than this are not paired for global decoding
+const CPR_AIRBORNE_MAX_AGE: Duration = Duration::from_secs(10);
+const CPR_SURFACE_MAX_AGE: Duration = Duration::from_secs(50);
+// Last known position of an aircraft is used as local reference this long
+const CPR_LOCAL_MAX_AGE: Duration = Duration::from_secs(600);
+// Local decoding around the receiver is only unambiguous within half a zone, ~180 NM airborne
+// and ~45 NM on the surface
+const CPR_RECEIVER_MAX_RANGE_KM: f64 = 333.0;
+const CPR_RECEIVER_MAX_RANGE_SURFACE_KM: f64 = 83.0;
+// CPR state of aircraft not heard for CPR_LOCAL_MAX_AGE is dropped this often
+const CPR_PRUNE_INTERVAL: Duration = Duration::from_secs(60);
+
+const CALL_SIGN_CHARSET: &[u8; 64] =
+    b"#ABCDEFGHIJKLMNOPQRSTUVWXYZ##### ###############0123456789######";
+
+#[derive(Debug, PartialEq)]
+pub enum AdsbError {
+    TooShort(usize),        // Frame length in bytes
+    UnsupportedDf(u8),      // Downlink format
+    CrcMismatch(u32),       // Remaining CRC syndrome
+    UnsupportedTypeCode(u8), // ADS-B type code
+    UnsupportedVelocitySubtype(u8), // Subtype of type code 19
+}
+
+impl fmt::Display for AdsbError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            AdsbError::TooShort(len) => write!(f, "frame too short ({} bytes)", len),
+            AdsbError::UnsupportedDf(df) => write!(f, "unsupported downlink format {}", df),
+            AdsbError::CrcMismatch(crc) => write!(f, "CRC mismatch ({:06X})", crc),
+            AdsbError::UnsupportedTypeCode(tc) => write!(f, "unsupported type code {}", tc),
+            AdsbError::UnsupportedVelocitySubtype(st) => write!(f, "unsupported velocity subtype {}", st),
+        }
+    }
+}
+
+impl std::error::Error for AdsbError {}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum AdsbMessage {
+    Identification {
+        category: u8,
+        call_sign: String,
+    },
+    AirbornePosition {
+        altitude: Option<f32>, // Feet, barometric or GNSS
+        position: Option<(f64, f64)>,
+        alert: bool,     // Surveillance status 1 or 2
+        emergency: bool, // Surveillance status 1
+        spi: bool,       // Surveillance status 3
+    },
+    SurfacePosition {
+        ground_speed: Option<f32>, // Knots
+        track: Option<f32>,        // Degrees
+        position: Option<(f64, f64)>,
+    },
+    Velocity {
+        ground_speed: Option<f32>,  // Knots, air speed for subtypes 3 and 4
+        track: Option<f32>,         // Degrees, heading for subtypes 3 and 4
+        vertical_rate: Option<f32>, // Feet per minute
+    },
+    OperationalStatus {
+        surface: bool,
+        version: u8,
+        nic_supplement_a: bool,
+        nac_p: u8,
+        sil: u8,
+    },
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct AdsbFrame {
+    pub hex_ident: String, // ICAO 24-bit address in hexadecimal
+    pub df: u8,
+    pub type_code: u8,
+    pub message: AdsbMessage,
+}
+
+#[derive(Clone, Copy)]
+struct CprFrame {
+    lat_cpr: f64, // Encoded latitude, 0..1
+    lon_cpr: f64, // Encoded longitude, 0..1
+    received: Instant,
+}
+
+#[derive(Default)]
+struct CprState {
+    even: Option<CprFrame>,
+    odd: Option<CprFrame>,
+    last_position: Option<(f64, f64, Instant)>,
+}
+
+impl CprState {
+    /// Time of the latest frame or position
+    fn last_received(&self) -> Option<Instant> {
+        [
+            self.even.map(|frame| frame.received),
+            self.odd.map(|frame| frame.received),
+            self.last_position.map(|(_, _, seen)| seen),
+        ]
+        .into_iter()
+        .flatten()
+        .max()
+    }
+}
+
+/// Decodes DF17/DF18 frames, keeps the CPR state of all aircraft.
+pub struct AdsbDecoder {
+    reference: Option<(f64, f64)>, // Receiver position, used for local CPR decoding
+    cpr_state: HashMap<String, CprState>,
+    last_pruned: Option<Instant>,
+}
+
+impl AdsbDecoder {
+    pub fn new(reference: Option<(f64, f64)>) -> AdsbDecoder {
+        AdsbDecoder {
+            reference,
+            cpr_state: HashMap::new(),
+            last_pruned: None,
+        }
+    }
+
+    /// Forget the CPR state of aircraft not heard for CPR_LOCAL_MAX_AGE, it is of no use anymore
+    fn prune(&mut self, now: Instant) {
+        let last_pruned = *self.last_pruned.get_or_insert(now);
+        if now.duration_since(last_pruned) < CPR_PRUNE_INTERVAL {
+            return;
+        }
+        self.last_pruned = Some(now);
+        self.cpr_state.retain(|_, state| {
+            state
+                .last_received()
+                .is_some_and(|received| now.duration_since(received) < CPR_LOCAL_MAX_AGE)
+        });
+    }
+
+    /// Decode a 14 byte extended squitter frame
+    pub fn decode(&mut self, frame: &[u8]) -> Result<AdsbFrame, AdsbError> {
+        self.decode_at(frame, Instant::now())
+    }
+
+    fn decode_at(&mut self, frame: &[u8], now: Instant) -> Result<AdsbFrame, AdsbError> {
+        if frame.is_empty() {
+            return Err(AdsbError::TooShort(0));
+        }
+        let df = frame[0] >> 3;
+        if df != 17 && df != 18 {
+            return Err(AdsbError::UnsupportedDf(df));
+        }
+        if frame.len() < 14 {
+            return Err(AdsbError::TooShort(frame.len()));
+        }
+        let crc = crc24(&frame[..14]);
+        if crc != 0 {
+            return Err(AdsbError::CrcMismatch(crc));
+        }
+        self.prune(now);
+
+        let hex_ident = format!("{:02X}{:02X}{:02X}", frame[1], frame[2], frame[3]);
+        let me = frame[4..11]
+            .iter()
+            .fold(0u64, |acc, &byte| (acc << 8) | byte as u64);
+        let type_code = me_bits(me, 1, 5) as u8;
+
+        let message = match type_code {
+            1..=4 => decode_identification(me),
+            5..=8 => {
+                let (ground_speed, track) = decode_movement(me);
+                let odd = me_bits(me, 22, 1) == 1;
+                let position = self.decode_position(&hex_ident, me, odd, true, now);
+                AdsbMessage::SurfacePosition {
+                    ground_speed,
+                    track,
+                    position,
+                }
+            }
+            9..=18 | 20..=22 => {
+                let status = me_bits(me, 6, 2);
+                let altitude = decode_altitude(me_bits(me, 9, 12) as u16, type_code >= 20);
+                let odd = me_bits(me, 22, 1) == 1;
+                let position = self.decode_position(&hex_ident, me, odd, false, now);
+                AdsbMessage::AirbornePosition {
+                    altitude,
+                    position,
+                    alert: status == 1 || status == 2,
+                    emergency: status == 1,
+                    spi: status == 3,
+                }
+            }
+            19 => decode_velocity(me)?,
+            31 => AdsbMessage::OperationalStatus {
+                surface: me_bits(me, 6, 3) == 1,
+                version: me_bits(me, 41, 3) as u8,
+                nic_supplement_a: me_bits(me, 44, 1) == 1,
+                nac_p: me_bits(me, 45, 4) as u8,
+                sil: me_bits(me, 51, 2) as u8,
+            },
+            _ => return Err(AdsbError::UnsupportedTypeCode(type_code)),
+        };
+
+        Ok(AdsbFrame {
+            hex_ident,
+            df,
+            type_code,
+            message,
+        })
+    }
+
+    /// Global decoding with an even/odd pair, local decoding with a reference otherwise
+    fn decode_position(
+        &mut self,
+        hex_ident: &str,
+        me: u64,
+        odd: bool,
+        surface: bool,
+        now: Instant,
+    ) -> Option<(f64, f64)> {
+        let cpr = CprFrame {
+            lat_cpr: me_bits(me, 23, 17) as f64 / CPR_MAX,
+            lon_cpr: me_bits(me, 40, 17) as f64 / CPR_MAX,
+            received: now,
+        };
+        let reference = self.reference;
+        let state = self.cpr_state.entry(hex_ident.to_string()).or_default();
+        if odd {
+            state.odd = Some(cpr);
+        } else {
+            state.even = Some(cpr);
+        }
+
+        let max_age = if surface {
+            CPR_SURFACE_MAX_AGE
+        } else {
+            CPR_AIRBORNE_MAX_AGE
+        };
+
+        // Local reference, the aircraft itself if recently seen or the receiver
+        let last_position = state
+            .last_position
+            .filter(|(_, _, seen)| now.duration_since(*seen) < CPR_LOCAL_MAX_AGE)
+            .map(|(lat, lon, _)| (lat, lon));
+
+        let mut position = None;
+        if let (Some(even), Some(odd_frame)) = (state.even, state.odd) {
+            let (older, newer) = if odd {
+                (even.received, odd_frame.received)
+            } else {
+                (odd_frame.received, even.received)
+            };
+            if newer.duration_since(older) < max_age {
+                position = if surface {
+                    // Surface positions are ambiguous by 90°, pick the solution closest to a reference
+                    last_position
+                        .or(reference)
+                        .and_then(|reference| cpr_global_surface(&even, &odd_frame, odd, reference))
+                } else {
+                    cpr_global_airborne(&even, &odd_frame, odd)
+                };
+            }
+        }
+        if position.is_none() {
+            if let Some(reference) = last_position {
+                position = Some(cpr_local(&cpr, odd, surface, reference));
+            } else if let Some(reference) = reference {
+                let local = cpr_local(&cpr, odd, surface, reference);
+                let max_range = if surface {
+                    CPR_RECEIVER_MAX_RANGE_SURFACE_KM
+                } else {
+                    CPR_RECEIVER_MAX_RANGE_KM
+                };
+                if distance_km(local, reference) < max_range {
+                    position = Some(local);
+                }
+            }
+        }
+
+        if let Some((lat, lon)) = position {
+            state.last_position = Some((lat, lon, now));
+        }
+        position
+    }
+}
+
+/// ICAO address of a DF11 all call reply, None if the parity doesn't match
+///
+/// The parity is overlaid with the interrogator code, so only its lower 7 bits may remain.
+pub fn all_call_address(frame: &[u8]) -> Option<String> {
+    if frame.len() < 7 || frame[0] >> 3 != 11 {
+        return None;
+    }
+    if crc24(&frame[..7]) & 0xFFFF80 != 0 {
+        return None;
+    }
+    Some(format!("{:02X}{:02X}{:02X}", frame[1], frame[2], frame[3]))
+}
+
+/// Bits of the 56 bit ME field, numbered 1 to 56 like in the specification
+fn me_bits(me: u64, start: u32, len: u32) -> u64 {
+    (me >> (57 - start - len)) & ((1u64 << len) - 1)
+}
+
+/// CRC-24 of a Mode S frame including its parity, 0 means no error
+fn crc24(frame: &[u8]) -> u32 {
+    const GENERATOR: u32 = 0xFFF409;
+    let mut crc: u32 = 0;
+    for &byte in &frame[..frame.len() - 3] {
+        crc ^= (byte as u32) << 16;
+        for _ in 0..8 {
+            crc <<= 1;
+            if crc & 0x1000000 != 0 {
+                crc ^= GENERATOR;
+            }
+        }
+    }
+    let parity = frame[frame.len() - 3..]
+        .iter()
+        .fold(0u32, |acc, &byte| (acc << 8) | byte as u32);
+    (crc & 0xFFFFFF) ^ parity
+}
+
+fn decode_identification(me: u64) -> AdsbMessage {
+    let call_sign: String = (0..8)
+        .map(|i| CALL_SIGN_CHARSET[me_bits(me, 9 + i * 6, 6) as usize] as char)
+        .filter(|&c| c != '#')
+        .collect();
+    AdsbMessage::Identification {
+        category: me_bits(me, 6, 3) as u8,
+        call_sign: call_sign.trim().to_string(),
+    }
+}
+
+/// 12 bit altitude field, only the 25 ft encoding (Q bit set) is supported
+fn decode_altitude(alt: u16, gnss: bool) -> Option<f32> {
+    if alt == 0 {
+        return None;
+    }
+    if gnss {
+        // GNSS height in meters
+        return Some(alt as f32 * 3.28084);
+    }
+    if alt & 0x10 == 0 {
+        // 100 ft Gillham code, rare for ADS-B
+        return None;
+    }
+    let n = ((alt & 0xFE0) >> 1) | (alt & 0x0F);
+    Some(n as f32 * 25.0 - 1000.0)
+}
+
+/// Ground speed and track of surface position messages
+fn decode_movement(me: u64) -> (Option<f32>, Option<f32>) {
+    let movement = me_bits(me, 6, 7) as f32;
+    let ground_speed = match movement as u8 {
+        1 => Some(0.0),
+        2..=8 => Some(0.125 + (movement - 2.0) * 0.125),
+        9..=12 => Some(1.0 + (movement - 9.0) * 0.25),
+        13..=38 => Some(2.0 + (movement - 13.0) * 0.5),
+        39..=93 => Some(15.0 + (movement - 39.0) * 1.0),
+        94..=108 => Some(70.0 + (movement - 94.0) * 2.0),
+        109..=123 => Some(100.0 + (movement - 109.0) * 5.0),
+        124 => Some(175.0),
+        _ => None,
+    };
+    let track = if me_bits(me, 13, 1) == 1 {
+        Some(me_bits(me, 14, 7) as f32 * 360.0 / 128.0)
+    } else {
+        None
+    };
+    (ground_speed, track)
+}
+
+fn decode_velocity(me: u64) -> Result<AdsbMessage, AdsbError> {
+    let subtype = me_bits(me, 6, 3);
+    let vertical_rate = match me_bits(me, 38, 9) {
+        0 => None,
+        vr => {
+            let rate = (vr as f32 - 1.0) * 64.0;
+            Some(if me_bits(me, 37, 1) == 1 { -rate } else { rate })
+        }
+    };
+
+    match subtype {
+        1 | 2 => {
+            // Supersonic subtype 2 uses 4 kt steps
+            let factor = if subtype == 2 { 4.0 } else { 1.0 };
+            let v_ew = me_bits(me, 15, 10);
+            let v_ns = me_bits(me, 26, 10);
+            if v_ew == 0 || v_ns == 0 {
+                return Ok(AdsbMessage::Velocity {
+                    ground_speed: None,
+                    track: None,
+                    vertical_rate,
+                });
+            }
+            let mut v_x = (v_ew as f32 - 1.0) * factor;
+            let mut v_y = (v_ns as f32 - 1.0) * factor;
+            if me_bits(me, 14, 1) == 1 {
+                v_x = -v_x; // Flying west
+            }
+            if me_bits(me, 25, 1) == 1 {
+                v_y = -v_y; // Flying south
+            }
+            let ground_speed = (v_x * v_x + v_y * v_y).sqrt();
+            let track = (v_x.atan2(v_y).to_degrees() + 360.0) % 360.0;
+            Ok(AdsbMessage::Velocity {
+                ground_speed: Some(ground_speed),
+                track: Some(track),
+                vertical_rate,
+            })
+        }
+        // Airspeed and heading, no ground velocity, only the vertical rate is used
+        3 | 4 => Ok(AdsbMessage::Velocity {
+            ground_speed: None,
+            track: None,
+            vertical_rate,
+        }),
+        _ => Err(AdsbError::UnsupportedVelocitySubtype(subtype as u8)),
+    }
+}
+
+/// Number of longitude zones at a given latitude
+fn cpr_nl(lat: f64) -> f64 {
+    let lat = lat.abs();
+    if lat == 0.0 {
+        return 59.0;
+    }
+    if lat == 87.0 {
+        return 2.0;
+    }
+    if lat > 87.0 {
+        return 1.0;
+    }
+    let a = 1.0 - (PI / (2.0 * CPR_NZ)).cos();
+    let b = (PI / 180.0 * lat).cos().powi(2);
+    (2.0 * PI / (1.0 - a / b).acos()).floor()
+}
+
+fn modulo(x: f64, y: f64) -> f64 {
+    x - y * (x / y).floor()
+}
+
+fn cpr_global_airborne(even: &CprFrame, odd: &CprFrame, odd_is_newer: bool) -> Option<(f64, f64)> {
+    let (lat, lon) = cpr_global(even, odd, odd_is_newer, 360.0)?;
+    let lat = if lat >= 270.0 { lat - 360.0 } else { lat };
+    Some((lat, normalize_lon(lon)))
+}
+
+fn cpr_global_surface(
+    even: &CprFrame,
+    odd: &CprFrame,
+    odd_is_newer: bool,
+    reference: (f64, f64),
+) -> Option<(f64, f64)> {
+    let (lat, lon) = cpr_global(even, odd, odd_is_newer, 90.0)?;
+
+    // Northern or southern hemisphere, pick the one of the reference
+    let lat = if reference.0 < 0.0 { lat - 90.0 } else { lat };
+
+    // Four possible longitudes, 90° apart
+    let lon = (0..4)
+        .map(|i| normalize_lon(lon + 90.0 * i as f64))
+        .min_by(|a, b| {
+            let da = (a - reference.1).abs().min(360.0 - (a - reference.1).abs());
+            let db = (b - reference.1).abs().min(360.0 - (b - reference.1).abs());
+            da.total_cmp(&db)
+        })?;
+    Some((lat, lon))
+}
+
+/// Global decoding, `span` is 360° for airborne and 90° for surface positions
+fn cpr_global(even: &CprFrame, odd: &CprFrame, odd_is_newer: bool, span: f64) -> Option<(f64, f64)> {
+    let d_lat_even = span / (4.0 * CPR_NZ);
+    let d_lat_odd = span / (4.0 * CPR_NZ - 1.0);
+
+    let j = (59.0 * even.lat_cpr - 60.0 * odd.lat_cpr + 0.5).floor();
+    let lat_even = d_lat_even * (modulo(j, 60.0) + even.lat_cpr);
+    let lat_odd = d_lat_odd * (modulo(j, 59.0) + odd.lat_cpr);
+
+    // Both frames have to be in the same longitude zone
+    let lat_even_norm = if lat_even >= 270.0 { lat_even - 360.0 } else { lat_even };
+    let lat_odd_norm = if lat_odd >= 270.0 { lat_odd - 360.0 } else { lat_odd };
+    if cpr_nl(lat_even_norm) != cpr_nl(lat_odd_norm) {
+        return None;
+    }
+
+    let (lat, nl, lon_cpr, i) = if odd_is_newer {
+        (lat_odd, cpr_nl(lat_odd_norm), odd.lon_cpr, 1.0)
+    } else {
+        (lat_even, cpr_nl(lat_even_norm), even.lon_cpr, 0.0)
+    };
+    let ni = (nl - i).max(1.0);
+    let d_lon = span / ni;
+    let m = (even.lon_cpr * (nl - 1.0) - odd.lon_cpr * nl + 0.5).floor();
+    let lon = d_lon * (modulo(m, ni) + lon_cpr);
+    Some((lat, lon))
+}
+
+/// Local decoding relative to a reference position within half a zone
+fn cpr_local(cpr: &CprFrame, odd: bool, surface: bool, reference: (f64, f64)) -> (f64, f64) {
+    let span = if surface { 90.0 } else { 360.0 };
+    let i = if odd { 1.0 } else { 0.0 };
+    let (lat_ref, lon_ref) = reference;
+
+    let d_lat = span / (4.0 * CPR_NZ - i);
+    let j = (lat_ref / d_lat).floor()
+        + (modulo(lat_ref, d_lat) / d_lat - cpr.lat_cpr + 0.5).floor();
+    let lat = d_lat * (j + cpr.lat_cpr);
+
+    let d_lon = span / (cpr_nl(lat) - i).max(1.0);
+    let m = (lon_ref / d_lon).floor()
+        + (modulo(lon_ref, d_lon) / d_lon - cpr.lon_cpr + 0.5).floor();
+    let lon = d_lon * (m + cpr.lon_cpr);
+    (lat, normalize_lon(lon))
+}
+
+fn normalize_lon(lon: f64) -> f64 {
+    let lon = modulo(lon, 360.0);
+    if lon >= 180.0 { lon - 360.0 } else { lon }
+}
+
+fn distance_km(a: (f64, f64), b: (f64, f64)) -> f64 {
+    crate::math::haversine_distance(a.0 as f32, a.1 as f32, b.0 as f32, b.1 as f32) as f64
+}
+
+/// Parse a hex string into bytes, e.g. from AVR or MQTT payloads
+pub fn hex_to_bytes(hex: &str) -> Option<Vec<u8>> {
+    if !hex.len().is_multiple_of(2) || !hex.is_ascii() {
+        return None;
+    }
+    (0..hex.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(&hex[i..i + 2], 16).ok())
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn decode_hex(decoder: &mut AdsbDecoder, hex: &str, now: Instant) -> AdsbFrame {
+        decoder.decode_at(&hex_to_bytes(hex).unwrap(), now).unwrap()
+    }
+
+    fn assert_position(position: Option<(f64, f64)>, lat: f64, lon: f64) {
+        let (p_lat, p_lon) = position.expect("position missing");
+        assert!((p_lat - lat).abs() < 0.0001, "lat {} != {}", p_lat, lat);
+        assert!((p_lon - lon).abs() < 0.0001, "lon {} != {}", p_lon, lon);
+    }
+
+    #[test]
+    fn test_identification() {
+        let mut decoder = AdsbDecoder::new(None);
+        let frame = decode_hex(&mut decoder, "8D4840D6202CC371C32CE0576098", Instant::now());
+        assert_eq!(frame.hex_ident, "4840D6");
+        assert_eq!(frame.type_code, 4);
+        assert_eq!(
+            frame.message,
+            AdsbMessage::Identification {
+                category: 0,
+                call_sign: "KLM1023".to_string()
+            }
+        );
+    }
+
+    #[test]
+    fn test_crc_mismatch() {
+        let mut decoder = AdsbDecoder::new(None);
+        let frame = hex_to_bytes("8D4840D6202CC371C32CE0576099").unwrap();
+        assert!(matches!(decoder.decode(&frame), Err(AdsbError::CrcMismatch(_))));
+    }
+
+    #[test]
+    fn test_all_call_address() {
+        // Interrogator code 0x16 remains in the parity
+        assert_eq!(all_call_address(&hex_to_bytes("5D484FDEA248F5").unwrap()), Some("484FDE".to_string()));
+        // Corrupted address
+        assert_eq!(all_call_address(&hex_to_bytes("5D484FDFA248F5").unwrap()), None);
+        // Not DF11
+        assert_eq!(all_call_address(&hex_to_bytes("8D4840D6202CC371C32CE0576098").unwrap()), None);
+    }
+
+    #[test]
+    fn test_airborne_position_global() {
+        let mut decoder = AdsbDecoder::new(None);
+        let now = Instant::now();
+        // Odd frame first, no reference, so no position yet
+        let frame = decode_hex(&mut decoder, "8D40621D58C386435CC412692AD6", now);
+        match frame.message {
+            AdsbMessage::AirbornePosition { altitude, position, .. } => {
+                assert_eq!(altitude, Some(38000.0));
+                assert_eq!(position, None);
+            }
+            _ => panic!("unexpected message {:?}", frame.message),
+        }
+        let frame = decode_hex(&mut decoder, "8D40621D58C382D690C8AC2863A7", now + Duration::from_secs(2));
+        match frame.message {
+            AdsbMessage::AirbornePosition { position, .. } => {
+                assert_position(position, 52.25720, 3.91937)
+            }
+            _ => panic!("unexpected message {:?}", frame.message),
+        }
+    }
+
+    #[test]
+    fn test_airborne_position_local() {
+        let mut decoder = AdsbDecoder::new(Some((52.258, 3.918)));
+        let frame = decode_hex(&mut decoder, "8D40621D58C382D690C8AC2863A7", Instant::now());
+        match frame.message {
+            AdsbMessage::AirbornePosition { position, .. } => {
+                assert_position(position, 52.25720, 3.91937)
+            }
+            _ => panic!("unexpected message {:?}", frame.message),
+        }
+    }
+
+    #[test]
+    fn test_surface_position() {
+        let mut decoder = AdsbDecoder::new(Some((51.990, 4.375)));
+        let now = Instant::now();
+        let frame = decode_hex(&mut decoder, "8C4841753AAB238733C8CD4020B1", now);
+        match frame.message {
+            AdsbMessage::SurfacePosition { ground_speed, track, .. } => {
+                assert_eq!(ground_speed, Some(18.0));
+                assert!((track.unwrap() - 140.625).abs() < 0.001);
+            }
+            _ => panic!("unexpected message {:?}", frame.message),
+        }
+        let frame = decode_hex(&mut decoder, "8C4841753A8A35323FAEBDAC702D", now + Duration::from_secs(1));
+        match frame.message {
+            AdsbMessage::SurfacePosition { position, .. } => {
+                assert_position(position, 52.32061, 4.73473)
+            }
+            _ => panic!("unexpected message {:?}", frame.message),
+        }
+    }
+
+    #[test]
+    fn test_prune() {
+        let mut decoder = AdsbDecoder::new(None);
+        let now = Instant::now();
+        decode_hex(&mut decoder, "8D40621D58C386435CC412692AD6", now);
+        decode_hex(&mut decoder, "8D485020994409940838175B284F", now + Duration::from_secs(500));
+        assert_eq!(decoder.cpr_state.len(), 1);
+        // Velocity frames don't keep CPR state, the position frame is older than CPR_LOCAL_MAX_AGE
+        decode_hex(&mut decoder, "8D485020994409940838175B284F", now + Duration::from_secs(700));
+        assert!(decoder.cpr_state.is_empty());
+    }
+
+    #[test]
+    fn test_velocity() {
+        let mut decoder = AdsbDecoder::new(None);
+        let frame = decode_hex(&mut decoder, "8D485020994409940838175B284F", Instant::now());
+        match frame.message {
+            AdsbMessage::Velocity { ground_speed, track, vertical_rate } => {
+                assert!((ground_speed.unwrap() - 159.20).abs() < 0.01);
+                assert!((track.unwrap() - 182.88).abs() < 0.01);
+                assert_eq!(vertical_rate, Some(-832.0));
+            }
+            _ => panic!("unexpected message {:?}", frame.message),
+        }
+
+        let frame = decode_hex(&mut decoder, "8DA05F219B06B6AF189400CBC33F", Instant::now());
+        match frame.message {
+            AdsbMessage::Velocity { ground_speed, track, vertical_rate } => {
+                // Subtype 3, airspeed and heading aren't ground speed and track
+                assert_eq!(ground_speed, None);
+                assert_eq!(track, None);
+                assert_eq!(vertical_rate, Some(-2304.0));
+            }
+            _ => panic!("unexpected message {:?}", frame.message),
+        }
+    }
+}
@@ -0,0 +1,117 @@
+// aircraft.json as written by readsb/dump1090-fa and read by tar1090.
+// Only the fields used by luftraum are mapped, everything else is ignored.
+// The same structs are served by the HTTP API, empty fields are left out there.
+
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Deserialize, Serialize)]
+pub struct AircraftJson {
+    pub now: Option<f64>,      // Unix time of the file
+    pub messages: Option<u64>, // Total number of messages received
+    pub aircraft: Vec<JsonAircraft>,
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone, Default)]
+pub struct JsonAircraft {
+    pub hex: String,                // ICAO address, '~' in front for non-ICAO addresses
+    #[serde(rename = "type", skip_serializing_if = "Option::is_none")]
+    pub source_type: Option<String>, // "adsb_icao", "mlat", ...
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub flight: Option<String>,     // Call sign, padded with spaces
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub alt_baro: Option<AltBaro>,  // Barometric altitude in feet or "ground"
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub gs: Option<f32>,            // Ground speed in knots
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub track: Option<f32>,         // Track angle in degrees
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub lat: Option<f64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub lon: Option<f64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub baro_rate: Option<f32>,     // Vertical rate in feet per minute
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub squawk: Option<String>,     // Four octal digits
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub emergency: Option<String>,  // "none", "general", "lifeguard", "minfuel", "nordo", ...
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub alert: Option<u8>,          // Flight status alert bit
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub spi: Option<u8>,            // Special position identification bit
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub messages: Option<u64>,      // Number of messages received
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub seen: Option<f32>,          // Seconds since any message was received
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub seen_pos: Option<f32>,      // Seconds since the position was updated
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub rssi: Option<f32>,          // Signal level in dBFS
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub r: Option<String>,          // Registration
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub t: Option<String>,          // ICAO type code
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub desc: Option<String>,       // Aircraft type description
+    #[serde(rename = "ownOp", skip_serializing_if = "Option::is_none")]
+    pub own_op: Option<String>,     // Owner or operator
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone, PartialEq)]
+#[serde(untagged)]
+pub enum AltBaro {
+    Feet(f32),
+    Ground(String),
+}
+
+impl JsonAircraft {
+    /// ICAO address like in SBS messages, upper case without '~'
+    pub fn hex_ident(&self) -> String {
+        self.hex.trim_start_matches('~').to_uppercase()
+    }
+
+    pub fn altitude(&self) -> Option<f32> {
+        match self.alt_baro {
+            Some(AltBaro::Feet(alt)) => Some(alt),
+            _ => None,
+        }
+    }
+
+    pub fn is_on_ground(&self) -> bool {
+        matches!(self.alt_baro, Some(AltBaro::Ground(_)))
+    }
+
+    pub fn is_emergency(&self) -> Option<bool> {
+        self.emergency.as_ref().map(|emergency| emergency != "none")
+    }
+}
+
+/// Parse a complete aircraft.json
+pub fn parse_aircraft_json(json: &str) -> Result<AircraftJson, serde_json::Error> {
+    serde_json::from_str(json)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_aircraft_json() {
+        let json = r#"{ "now" : 1700000000.1, "messages" : 42, "aircraft" : [
+            {"hex":"3c6586","flight":"DLH9U   ","alt_baro":36000,"gs":447.2,"track":112.5,
+             "baro_rate":-64,"squawk":"1000","emergency":"none","lat":53.61,"lon":9.98,
+             "seen_pos":0.4,"seen":0.1,"rssi":-21.4},
+            {"hex":"~3c4b2a","alt_baro":"ground","seen":2.0}
+        ]}"#;
+        let data = parse_aircraft_json(json).unwrap();
+        assert_eq!(data.messages, Some(42));
+        assert_eq!(data.aircraft.len(), 2);
+        let aircraft = &data.aircraft[0];
+        assert_eq!(aircraft.hex_ident(), "3C6586");
+        assert_eq!(aircraft.altitude(), Some(36000.0));
+        assert_eq!(aircraft.is_emergency(), Some(false));
+        let aircraft = &data.aircraft[1];
+        assert_eq!(aircraft.hex_ident(), "3C4B2A");
+        assert!(aircraft.is_on_ground());
+        assert_eq!(aircraft.altitude(), None);
+    }
+}
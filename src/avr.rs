@@ -0,0 +1,69 @@
+// AVR text format as served by dump1090/readsb on port 30002.
+//
+// One frame per line, e.g. "*8D4840D6202CC371C32CE0576098;" or with a 12 MHz timestamp
+// in front of the frame "@0000B2D25C2C8D4840D6202CC371C32CE0576098;".
+
+use crate::adsb::hex_to_bytes;
+use crate::beast::MLAT_TIMESTAMP;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct AvrFrame {
+    pub timestamp: Option<u64>, // 48 bit counter of the receiver's 12 MHz clock
+    pub data: Vec<u8>,          // Mode A/C or Mode S message
+}
+
+impl AvrFrame {
+    /// Frames synthesized from multilateration results carry a magic timestamp
+    pub fn is_mlat(&self) -> bool {
+        self.timestamp == Some(MLAT_TIMESTAMP)
+    }
+}
+
+/// Parse one AVR line, `None` if it is no valid frame
+pub fn parse_avr(line: &str) -> Option<AvrFrame> {
+    let line = line.trim();
+    let body = line.strip_suffix(';')?;
+
+    let (timestamp, hex) = if let Some(hex) = body.strip_prefix('*') {
+        (None, hex)
+    } else if let Some(body) = body.strip_prefix('@') {
+        if body.len() < 12 || !body.is_ascii() {
+            return None;
+        }
+        let timestamp = u64::from_str_radix(&body[..12], 16).ok()?;
+        (Some(timestamp), &body[12..])
+    } else {
+        return None;
+    };
+
+    let data = hex_to_bytes(hex)?;
+    // Mode A/C (2), short (7) and long (14) Mode S frames
+    if data.len() != 2 && data.len() != 7 && data.len() != 14 {
+        return None;
+    }
+    Some(AvrFrame { timestamp, data })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_avr() {
+        let frame = parse_avr("*8D4840D6202CC371C32CE0576098;").unwrap();
+        assert_eq!(frame.timestamp, None);
+        assert_eq!(frame.data.len(), 14);
+        assert!(!frame.is_mlat());
+
+        let frame = parse_avr("@0000B2D25C2C5D4840D6A1B2C3;").unwrap();
+        assert_eq!(frame.timestamp, Some(0x0000B2D25C2C));
+        assert_eq!(frame.data.len(), 7);
+
+        let frame = parse_avr("@FF004D4C41548D4840D6202CC371C32CE0576098;").unwrap();
+        assert!(frame.is_mlat());
+
+        assert_eq!(parse_avr("*8D4840D6202CC371C32CE05760;"), None);
+        assert_eq!(parse_avr("8D4840D6202CC371C32CE0576098"), None);
+        assert_eq!(parse_avr("*8D4840D6202CC371C32CE057609X;"), None);
+    }
+}
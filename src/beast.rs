@@ -0,0 +1,224 @@
+// Beast binary format as served by dump1090/readsb on port 30005.
+//
+// Every frame starts with 0x1a followed by a type byte, a 6 byte timestamp (12 MHz clock),
+// one signal level byte and the Mode A/C or Mode S payload. Any 0x1a inside the frame is
+// escaped by doubling it.
+
+use crate::adsb::hex_to_bytes;
+
+const BEAST_ESCAPE: u8 = 0x1a;
+
+// Timestamp of frames synthesized from multilateration results, "\xFF\0MLAT"
+pub const MLAT_TIMESTAMP: u64 = 0xFF004D4C4154;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum BeastFrameType {
+    ModeAc,     // '1', 2 bytes
+    ModeSShort, // '2', 7 bytes
+    ModeSLong,  // '3', 14 bytes
+    Status,     // '4', receiver status, 14 bytes
+}
+
+impl BeastFrameType {
+    fn from_byte(byte: u8) -> Option<BeastFrameType> {
+        match byte {
+            b'1' => Some(BeastFrameType::ModeAc),
+            b'2' => Some(BeastFrameType::ModeSShort),
+            b'3' => Some(BeastFrameType::ModeSLong),
+            b'4' => Some(BeastFrameType::Status),
+            _ => None,
+        }
+    }
+
+    fn payload_len(&self) -> usize {
+        match self {
+            BeastFrameType::ModeAc => 2,
+            BeastFrameType::ModeSShort => 7,
+            BeastFrameType::ModeSLong => 14,
+            BeastFrameType::Status => 14,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct BeastFrame {
+    pub frame_type: BeastFrameType,
+    pub timestamp: u64,  // 48 bit counter of the receiver's 12 MHz clock
+    pub signal_level: u8, // Raw signal level, 0-255
+    pub data: Vec<u8>,    // Mode A/C or Mode S message
+}
+
+impl BeastFrame {
+    /// Signal level in dBFS, 255 equals 0 dBFS
+    pub fn rssi(&self) -> f32 {
+        let level = self.signal_level as f32 / 255.0;
+        10.0 * (level * level + 1.125e-5).log10()
+    }
+
+    /// Frames synthesized from multilateration results carry a magic timestamp
+    pub fn is_mlat(&self) -> bool {
+        self.timestamp == MLAT_TIMESTAMP
+    }
+
+    /// Timestamp in seconds since the receiver's clock started
+    pub fn timestamp_secs(&self) -> f64 {
+        self.timestamp as f64 / 12_000_000.0
+    }
+
+    /// Raw message as upper case hex string, used for logging
+    pub fn to_hex(&self) -> String {
+        self.data.iter().map(|byte| format!("{:02X}", byte)).collect()
+    }
+
+    /// Timestamp, signal level and message as written to raw_messages.log, e.g. "000102030405,80,8D48..."
+    pub fn to_log(&self) -> String {
+        format!("{:012X},{:02X},{}", self.timestamp, self.signal_level, self.to_hex())
+    }
+
+    /// Counterpart of `to_log`, used for replays
+    pub fn from_log(line: &str) -> Option<BeastFrame> {
+        let mut fields = line.trim().split(',');
+        let timestamp = u64::from_str_radix(fields.next()?, 16).ok()?;
+        let signal_level = u8::from_str_radix(fields.next()?, 16).ok()?;
+        let data = hex_to_bytes(fields.next()?)?;
+        let frame_type = match data.len() {
+            2 => BeastFrameType::ModeAc,
+            7 => BeastFrameType::ModeSShort,
+            14 => BeastFrameType::ModeSLong,
+            _ => return None,
+        };
+        Some(BeastFrame { frame_type, timestamp, signal_level, data })
+    }
+}
+
+/// Collects bytes from a Beast stream and splits them into frames.
+#[derive(Default)]
+pub struct BeastDecoder {
+    buffer: Vec<u8>,
+}
+
+impl BeastDecoder {
+    pub fn new() -> BeastDecoder {
+        BeastDecoder { buffer: Vec::new() }
+    }
+
+    /// Append received bytes, frames are taken out with `next_frame`
+    pub fn push(&mut self, bytes: &[u8]) {
+        self.buffer.extend_from_slice(bytes);
+    }
+
+    /// Returns the next complete frame, `None` if more data is needed
+    pub fn next_frame(&mut self) -> Option<BeastFrame> {
+        loop {
+            // Sync to the next frame start, everything in front of it is garbage
+            let start = self.buffer.iter().position(|&byte| byte == BEAST_ESCAPE);
+            match start {
+                Some(start) => {
+                    self.buffer.drain(..start);
+                }
+                None => {
+                    self.buffer.clear();
+                    return None;
+                }
+            }
+            if self.buffer.len() < 2 {
+                return None;
+            }
+
+            let frame_type = match BeastFrameType::from_byte(self.buffer[1]) {
+                Some(frame_type) => frame_type,
+                None => {
+                    // Escaped 0x1a or unknown type, skip and resync
+                    self.buffer.drain(..2);
+                    continue;
+                }
+            };
+
+            // Timestamp (6) + signal level (1) + payload, all of it may be escaped
+            let wanted = 7 + frame_type.payload_len();
+            let mut unescaped = Vec::with_capacity(wanted);
+            let mut pos = 2;
+            while unescaped.len() < wanted {
+                match self.buffer.get(pos) {
+                    None => return None, // Incomplete frame, wait for more data
+                    Some(&BEAST_ESCAPE) => match self.buffer.get(pos + 1) {
+                        None => return None,
+                        Some(&BEAST_ESCAPE) => {
+                            unescaped.push(BEAST_ESCAPE);
+                            pos += 2;
+                        }
+                        Some(_) => break, // Unescaped 0x1a starts a new frame, this one is broken
+                    },
+                    Some(&byte) => {
+                        unescaped.push(byte);
+                        pos += 1;
+                    }
+                }
+            }
+            self.buffer.drain(..pos);
+            if unescaped.len() < wanted {
+                continue;
+            }
+
+            let timestamp = unescaped[..6]
+                .iter()
+                .fold(0u64, |acc, &byte| (acc << 8) | byte as u64);
+            return Some(BeastFrame {
+                frame_type,
+                timestamp,
+                signal_level: unescaped[6],
+                data: unescaped[7..].to_vec(),
+            });
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_beast_long_frame() {
+        let mut decoder = BeastDecoder::new();
+        // DF17 identification of KLM1023 with timestamp 0x000102030405 and signal 0x80
+        let mut stream = vec![0x1a, b'3', 0x00, 0x01, 0x02, 0x03, 0x04, 0x05, 0x80];
+        stream.extend_from_slice(&[
+            0x8D, 0x48, 0x40, 0xD6, 0x20, 0x2C, 0xC3, 0x71, 0xC3, 0x2C, 0xE0, 0x57, 0x60, 0x98,
+        ]);
+        decoder.push(&stream[..10]);
+        assert_eq!(decoder.next_frame(), None);
+        decoder.push(&stream[10..]);
+        let frame = decoder.next_frame().unwrap();
+        assert_eq!(frame.frame_type, BeastFrameType::ModeSLong);
+        assert_eq!(frame.timestamp, 0x000102030405);
+        assert_eq!(frame.signal_level, 0x80);
+        assert_eq!(frame.to_hex(), "8D4840D6202CC371C32CE0576098");
+        assert_eq!(decoder.next_frame(), None);
+    }
+
+    #[test]
+    fn test_beast_log() {
+        let frame = BeastFrame::from_log("000102030405,80,8D4840D6202CC371C32CE0576098").unwrap();
+        assert_eq!(frame.frame_type, BeastFrameType::ModeSLong);
+        assert_eq!(frame.timestamp, 0x000102030405);
+        assert_eq!(frame.signal_level, 0x80);
+        assert_eq!(frame.to_log(), "000102030405,80,8D4840D6202CC371C32CE0576098");
+        assert_eq!(BeastFrame::from_log("000102030405,80,8D4840"), None);
+    }
+
+    #[test]
+    fn test_beast_escaped_bytes() {
+        let mut decoder = BeastDecoder::new();
+        // Garbage in front, escaped 0x1a in timestamp and payload
+        let stream = vec![
+            0xff, 0x00, 0x1a, b'2', 0x00, 0x00, 0x00, 0x00, 0x1a, 0x1a, 0x01, 0x40, 0x5D, 0x1a,
+            0x1a, 0x02, 0x03, 0x04, 0x05, 0x06,
+        ];
+        decoder.push(&stream);
+        let frame = decoder.next_frame().unwrap();
+        assert_eq!(frame.frame_type, BeastFrameType::ModeSShort);
+        assert_eq!(frame.timestamp, 0x1a01);
+        assert_eq!(frame.signal_level, 0x40);
+        assert_eq!(frame.data, vec![0x5D, 0x1a, 0x02, 0x03, 0x04, 0x05, 0x06]);
+    }
+}
@@ -1,23 +1,247 @@
 use crate::hex_lookup::{fetch_aircraft, Aircraft};
 use crate::math::haversine_distance;
-use chrono::{NaiveDate, NaiveTime};
-use std::collections::HashMap;
+use crate::motion::{MotionFilter, MotionState, PositionAccuracy};
+use crate::plausibility::{Plausibility, PlausibilityState, Rejection};
+use crate::sbs::{SbsMessage, SbsStatus};
+use chrono::{DateTime, NaiveDate, NaiveTime, Utc};
+use std::collections::{HashMap, VecDeque};
+use std::hash::{DefaultHasher, Hash, Hasher};
+use std::net::SocketAddr;
+use std::time::{Duration, Instant};
 use std::sync::{Arc, Mutex};
 use bevy::prelude::Resource;
 use once_cell::sync::Lazy;
+use serde::{Deserialize, Serialize};
+use tokio::sync::broadcast;
+
+// Lines buffered for the SBS output, clients lagging behind lose the oldest lines
+const SBS_OUTPUT_CAPACITY: usize = 4096;
+// Changes buffered for subscribers, e.g. WebSocket clients
+const CHANGES_CAPACITY: usize = 4096;
+// The same message of another source within this window is a duplicate of the same transmission
+const DUPLICATE_WINDOW: Duration = Duration::from_secs(2);
+// Age states of all aircraft are checked this often, see Expiry
+const EXPIRY_INTERVAL: Duration = Duration::from_secs(1);
+// Lifecycle events kept per aircraft, the oldest are dropped
+const MAX_EVENTS: usize = 32;
 
 // All ADS-B data is stored and shared between network and Bevy in here
 pub struct SharedDataDb {
     plane_db: HashMap<String, PlaneDataSet>, // PlaneID and related data
+    dropped_messages: HashMap<String, usize>, // Reason and number of dropped messages
+    source_heartbeat: HashMap<String, DateTime<Utc>>, // Source label and time of its last CLK record
+    sbs_output: broadcast::Sender<String>,     // Every accepted message re-encoded as SBS line
+    changes: broadcast::Sender<AircraftChange>, // New, changed and removed aircraft
+    source_counters: HashMap<String, SourceCounters>, // Source label and its message counters
+    generation: u64, // Increased on every restore, the 3D scene is rebuilt then
+    history: History, // Bounds of the samples kept per aircraft
+    plausibility: Plausibility, // Limits of positions and altitudes
+    receiver: Option<(f32, f32)>, // Receiver position, reference of the range check
+}
+
+// Bounds of the history of every aircraft, older samples are dropped
+#[derive(Debug, Deserialize, Clone, Copy, PartialEq)]
+pub struct History {
+    #[serde(default = "default_history_depth")]
+    pub depth: usize, // Samples per aircraft
+    #[serde(default = "default_history_window_minutes")]
+    pub window_minutes: i64, // Relative to the newest sample of the aircraft
+}
+
+impl Default for History {
+    fn default() -> Self {
+        History {
+            depth: default_history_depth(),
+            window_minutes: default_history_window_minutes(),
+        }
+    }
+}
+
+// Ageing of aircraft by the time since their last position and their last message
+#[derive(Debug, Deserialize, Clone, Copy, PartialEq)]
+pub struct Expiry {
+    #[serde(default = "default_position_timeout_secs")]
+    pub position_timeout_secs: u64, // Without new position the aircraft is stale
+    #[serde(default = "default_lost_timeout_secs")]
+    pub lost_timeout_secs: u64, // Without any message the aircraft is lost
+    #[serde(default = "default_identity_timeout_secs")]
+    pub identity_timeout_secs: u64, // Without any message the aircraft is removed
+}
+
+impl Default for Expiry {
+    fn default() -> Self {
+        Expiry {
+            position_timeout_secs: default_position_timeout_secs(),
+            lost_timeout_secs: default_lost_timeout_secs(),
+            identity_timeout_secs: default_identity_timeout_secs(),
+        }
+    }
+}
+
+fn default_position_timeout_secs() -> u64 {
+    15
+}
+
+fn default_lost_timeout_secs() -> u64 {
+    30
+}
+
+fn default_identity_timeout_secs() -> u64 {
+    60
+}
+
+impl Expiry {
+    /// Age state by the time since the last message and the last position, if any
+    pub fn age_state(&self, since_received: Duration, since_position: Option<Duration>) -> AgeState {
+        if since_received >= Duration::from_secs(self.lost_timeout_secs) {
+            AgeState::Lost
+        } else if since_position.unwrap_or(since_received) >= Duration::from_secs(self.position_timeout_secs) {
+            AgeState::Stale
+        } else {
+            AgeState::Active
+        }
+    }
+}
+
+// Stale and lost aircraft are shown ghosted until they are removed
+#[derive(Debug, Clone, Copy, PartialEq, Default, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum AgeState {
+    #[default]
+    Active, // Recent position
+    Stale,  // Position timed out, still receiving other messages
+    Lost,   // Nothing received lately
+}
+
+fn default_history_depth() -> usize {
+    2000
+}
+
+fn default_history_window_minutes() -> i64 {
+    60
+}
+
+// Copy of all aircraft data, e.g. to seek in a replay
+#[derive(Clone, Default)]
+pub struct PlaneDbSnapshot {
+    plane_db: HashMap<String, PlaneDataSet>,
+}
+
+// Messages of one source, duplicates have been stored via another source before
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct SourceCounters {
+    pub messages: usize,
+    pub duplicates: usize,
+    pub rejected: usize, // Messages with an implausible position or altitude, see plausibility
 }
 
+// Reception of one aircraft by one source (receiver)
+#[derive(Debug, Clone, PartialEq)]
+pub struct SourceReception {
+    pub first_heard: DateTime<Utc>,
+    pub last_heard: DateTime<Utc>,
+    pub counters: SourceCounters,
+    pub signal_level: Option<f32>, // Latest signal level in dBFS, Beast sources only
+}
+
+impl SourceReception {
+    fn new() -> SourceReception {
+        let now = Utc::now();
+        SourceReception {
+            first_heard: now,
+            last_heard: now,
+            counters: SourceCounters::default(),
+            signal_level: None,
+        }
+    }
+}
+
+// One position of an aircraft's history
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct TrackPoint {
+    pub time: Option<DateTime<Utc>>, // Generation time of the message
+    pub latitude: f32,
+    pub longitude: f32,
+    pub altitude: Option<f32>,      // Feet
+    pub ground_speed: Option<f32>,  // Knots
+    pub track: Option<f32>,         // Degrees
+    pub vertical_rate: Option<f32>, // Feet per minute
+    pub squawk: Option<i32>,
+    pub source: String,
+}
+
+// Change of an aircraft, subscribers read the current state with `get_aircraft_state`
+#[derive(Debug, Clone, PartialEq)]
+pub enum AircraftChange {
+    New(String),     // Hex ident of a new aircraft
+    Changed(String), // Hex ident of an updated aircraft
+    Removed(String), // Hex ident of an expired aircraft
+}
+
+impl AircraftChange {
+    pub fn hex_ident(&self) -> &str {
+        match self {
+            AircraftChange::New(hex_ident)
+            | AircraftChange::Changed(hex_ident)
+            | AircraftChange::Removed(hex_ident) => hex_ident,
+        }
+    }
+}
+
+// Current state of an aircraft, merged from all sources
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct AircraftState {
+    pub hex_ident: String,
+    pub call_sign: Option<String>,
+    pub latitude: Option<f32>,
+    pub longitude: Option<f32>,
+    pub altitude: Option<f32>,          // Feet
+    pub ground_speed: Option<f32>,      // Knots
+    pub track: Option<f32>,             // Degrees
+    pub vertical_rate: Option<f32>,     // Feet per minute
+    pub squawk: Option<i32>,
+    pub is_on_ground: Option<bool>,
+    pub position_source: Option<PositionSource>,
+    pub last_seen: usize,               // Seconds
+    pub age_state: AgeState,
+    pub motion: Option<MotionState>,    // Filtered state at the latest message, see motion
+    pub status: Option<SbsStatus>,      // Latest status reported by a STA record
+    pub receivers: usize,               // Sources that heard the aircraft
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum PositionSource {
+    Adsb, // Reported by the aircraft itself
+    Mlat, // Derived from multilateration
+}
+
+// Lifecycle events of BaseStation SEL, ID, AIR and STA records
+#[derive(Debug, Clone, PartialEq)]
+pub enum AircraftEvent {
+    Appeared,                // AIR, new aircraft
+    Selected,                // SEL, selected in the source's UI
+    CallSignChanged(String), // ID, new or changed call sign
+    StatusChanged(SbsStatus), // STA, e.g. position lost
+}
+
+#[derive(Clone)]
 struct PlaneDataSet {
     plane_id: String,           // Redundant to hex_ident
-    last_seen: usize,           // Set to 0 on new or updated entries
+    last_received: Instant,     // Time of the last message
+    last_position: Option<Instant>, // Time of the last position, ADS-B or multilateration
+    age_state: AgeState,        // Updated by update_age_states
     data_const: PlaneDataConst, // Store all fixed plane data
     data_var: PlaneDataVar,     // Store variable plane data
+    status: Option<SbsStatus>,  // Latest status reported by a STA record
+    events: VecDeque<(DateTime<Utc>, AircraftEvent)>, // Latest lifecycle events, see MAX_EVENTS
+    receivers: HashMap<String, SourceReception>, // Sources that heard the aircraft
+    recent: VecDeque<(Instant, u64, String)>, // Fingerprint and source of recent messages, see DUPLICATE_WINDOW
+    plausibility: PlausibilityState, // Last accepted position and altitude
+    motion: MotionFilter,            // Filtered position and velocity
 }
 
+#[derive(Clone)]
 struct PlaneDataConst {
     session_id: Option<String>,  // Session ID (optional, often empty)
     aircraft_id: Option<String>, // Aircraft ID (optional, often empty)
@@ -26,79 +250,380 @@ struct PlaneDataConst {
     call_sign: Option<String>,   // Aircraft call_sign (optional)
 }
 
+// One decoded message of an aircraft
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Sample {
+    pub time: DateTime<Utc>,         // Time the message was generated
+    pub source: String,              // Label of the source the message was received from
+    pub transmission_type: usize,    // Transmission type (e.g., 1, 2, 3, etc.)
+    pub altitude: Option<f32>,       // Altitude in feet
+    pub ground_speed: Option<f32>,   // Ground speed in knots
+    pub track: Option<f32>,          // Track angle in degrees
+    pub latitude: Option<f32>,       // Latitude in decimal degrees
+    pub longitude: Option<f32>,      // Longitude in decimal degrees
+    pub vertical_rate: Option<f32>,  // Vertical rate in feet per minute
+    pub squawk: Option<i32>,         // Transponder squawk code
+    pub alert: Option<bool>,         // Alert flag (true if squawk has changed)
+    pub emergency: Option<bool>,     // Emergency flag (true if emergency code is set)
+    pub spi: Option<bool>,           // Special Position Indicator flag
+    pub is_on_ground: Option<bool>,  // Ground status flag
+    pub rejected: Option<Rejection>, // Implausible position or altitude, removed from the sample
+}
+
+impl Sample {
+    /// Values of a newer sample, fields it doesn't carry are kept
+    fn merge(&mut self, newer: &Sample) {
+        self.time = newer.time;
+        self.source = newer.source.clone();
+        self.transmission_type = newer.transmission_type;
+        self.altitude = newer.altitude.or(self.altitude);
+        self.ground_speed = newer.ground_speed.or(self.ground_speed);
+        self.track = newer.track.or(self.track);
+        self.latitude = newer.latitude.or(self.latitude);
+        self.longitude = newer.longitude.or(self.longitude);
+        self.vertical_rate = newer.vertical_rate.or(self.vertical_rate);
+        self.squawk = newer.squawk.or(self.squawk);
+        self.alert = newer.alert.or(self.alert);
+        self.emergency = newer.emergency.or(self.emergency);
+        self.spi = newer.spi.or(self.spi);
+        self.is_on_ground = newer.is_on_ground.or(self.is_on_ground);
+        self.rejected = newer.rejected;
+    }
+}
+
+// Position derived from multilateration
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct MlatSample {
+    time: DateTime<Utc>,
+    latitude: f32,
+    longitude: f32,
+    altitude: Option<f32>, // Feet
+}
+
+// Beast frame reception
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct BeastReception {
+    time: DateTime<Utc>,
+    beast_timestamp: u64, // Receiver 12 MHz clock
+    signal_level: f32,    // dBFS
+}
+
+// Time-stamped history of an aircraft, every buffer is bounded by `History`
+#[derive(Clone, Default)]
 struct PlaneDataVar {
-    message_type: Vec<String>,       // Message type (e.g., "MSG")
-    transmission_type: Vec<usize>,   // Transmission type (e.g., 1, 2, 3, etc.)
-    generated_date: Vec<NaiveDate>,  // Date the message was generated (UTC)
-    generated_time: Vec<NaiveTime>,  // Time the message was generated (UTC)
-    logged_date: Vec<NaiveDate>,     // Date the message was logged (UTC)
-    logged_time: Vec<NaiveTime>,     // Time the message was logged (UTC)
-    altitude: Vec<Option<f32>>,      // Altitude in feet (optional)
-    ground_speed: Vec<Option<f32>>,  // Ground speed in knots (optional)
-    track: Vec<Option<f32>>,         // Track angle in degrees (optional)
-    latitude: Vec<Option<f32>>,      // Latitude in decimal degrees (optional)
-    longitude: Vec<Option<f32>>,     // Longitude in decimal degrees (optional)
-    vertical_rate: Vec<Option<f32>>, // Vertical rate in feet per minute (optional)
-    squawk: Vec<Option<i32>>,        // Transponder squawk code (optional)
-    alert: Vec<Option<bool>>,        // Alert flag (true if squawk has changed)
-    emergency: Vec<Option<bool>>,    // Emergency flag (true if emergency code is set)
-    spi: Vec<Option<bool>>,          // Special Position Indicator flag
-    is_on_ground: Vec<Option<bool>>, // Ground status flag
+    samples: VecDeque<Sample>,           // Decoded messages, oldest first
+    mlat: VecDeque<MlatSample>,          // Multilateration results, kept apart from ADS-B positions
+    receptions: VecDeque<BeastReception>, // Timestamps and signal levels of Beast frames
+    messages: usize,                     // All stored messages, including the dropped ones
+}
+
+/// Insert into a bounded buffer in time order, drops samples beyond the depth or older than the window.
+/// Sources timed by their sender and by reception deliver out of order, mostly close to the back
+fn push_bounded<T>(buffer: &mut VecDeque<T>, value: T, history: &History, time: impl Fn(&T) -> DateTime<Utc>) {
+    let index = buffer
+        .iter()
+        .rposition(|other| time(other) <= time(&value))
+        .map_or(0, |index| index + 1);
+    buffer.insert(index, value);
+    while buffer.len() > history.depth.max(1) {
+        buffer.pop_front();
+    }
+    let newest = buffer.back().map(&time).unwrap_or_default();
+    let oldest = newest - chrono::Duration::minutes(history.window_minutes);
+    while buffer.front().is_some_and(|value| time(value) < oldest) {
+        buffer.pop_front();
+    }
+}
+
+impl PlaneDataVar {
+    fn push(&mut self, sample: Sample, history: &History) {
+        self.messages += 1;
+        push_bounded(&mut self.samples, sample, history, |sample| sample.time);
+    }
+
+    /// Latest known value of a field
+    fn latest<T>(&self, field: impl Fn(&Sample) -> Option<T>) -> Option<T> {
+        self.samples.iter().rev().find_map(field)
+    }
+
+    /// Latest known value of every field at `time`, `None` before the first sample
+    fn sample_at(&self, time: DateTime<Utc>) -> Option<Sample> {
+        let mut samples = self.samples.iter().filter(|sample| sample.time <= time);
+        let mut merged = samples.next()?.clone();
+        for sample in samples {
+            merged.merge(sample);
+        }
+        Some(merged)
+    }
+
+    fn samples_since(&self, since: DateTime<Utc>) -> Vec<Sample> {
+        self.samples
+            .iter()
+            .filter(|sample| sample.time >= since)
+            .cloned()
+            .collect()
+    }
 }
 
 impl SharedDataDb {
     pub fn new() -> SharedDataDb {
         SharedDataDb {
             plane_db: HashMap::<String, PlaneDataSet>::new(),
+            dropped_messages: HashMap::new(),
+            source_heartbeat: HashMap::new(),
+            sbs_output: broadcast::channel(SBS_OUTPUT_CAPACITY).0,
+            changes: broadcast::channel(CHANGES_CAPACITY).0,
+            source_counters: HashMap::new(),
+            generation: 0,
+            history: History::default(),
+            plausibility: Plausibility::default(),
+            receiver: None,
         }
     }
 
+    /// Bounds of the history, applied to new samples
+    pub fn set_history(&mut self, history: History) {
+        self.history = history;
+    }
+
+    /// Limits of new positions and altitudes, the range is checked only with a receiver position
+    pub fn set_plausibility(&mut self, plausibility: Plausibility, receiver: Option<(f32, f32)>) {
+        self.plausibility = plausibility;
+        self.receiver = receiver;
+    }
+
     pub fn get_planes_id(&self) -> Vec<&str> {
         let list_of_planes: Vec<&str> = self.plane_db.keys().map(|s| s.as_str()).collect();
         list_of_planes
     }
 
-    pub fn increase_last_seen(&mut self, value_sec: usize) {
-        // Increase last_seen of all plane entries
-        for (_key, value) in self.plane_db.iter_mut() {
-            value.last_seen += value_sec;
+    /// Count a message that could not be decoded
+    pub fn count_dropped(&mut self, reason: &str) {
+        self.count_dropped_many(reason, 1);
+    }
+
+    /// Count several messages dropped at once, e.g. lost by a lagging receiver
+    pub fn count_dropped_many(&mut self, reason: &str, count: usize) {
+        *self.dropped_messages.entry(reason.to_string()).or_insert(0) += count;
+    }
+
+    /// Returns number of dropped messages per reason, sorted by reason
+    pub fn get_dropped_messages(&self) -> Vec<(String, usize)> {
+        let mut dropped: Vec<(String, usize)> = self
+            .dropped_messages
+            .iter()
+            .map(|(reason, count)| (reason.clone(), *count))
+            .collect();
+        dropped.sort();
+        dropped
+    }
+
+    /// Update the age state of all aircraft, subscribers are notified of every change
+    pub fn update_age_states(&mut self, expiry: &Expiry) {
+        for (plane_id, p_dataset) in self.plane_db.iter_mut() {
+            let age_state = expiry.age_state(
+                p_dataset.last_received.elapsed(),
+                p_dataset.last_position.map(|last_position| last_position.elapsed()),
+            );
+            if age_state != p_dataset.age_state {
+                p_dataset.age_state = age_state;
+                let _ = self.changes.send(AircraftChange::Changed(plane_id.clone()));
+            }
         }
     }
 
+    /// Remove aircraft without messages for `timeout`, returns their hex idents
+    pub fn expire_planes(&mut self, timeout: Duration) -> Vec<String> {
+        let expired: Vec<String> = self
+            .plane_db
+            .iter()
+            .filter(|(_, p_dataset)| p_dataset.last_received.elapsed() >= timeout)
+            .map(|(plane_id, _)| plane_id.clone())
+            .collect();
+        for plane_id in expired.iter() {
+            self.remove_plane(plane_id.clone());
+        }
+        expired
+    }
+
+    /// Seconds since the last message, `usize::MAX` for unknown (removed) planes
     pub fn get_last_seen(&self, plane_id: String) -> usize {
-        self.plane_db.get(&plane_id).unwrap().last_seen
+        self.plane_db
+            .get(&plane_id)
+            .map_or(usize::MAX, |p_dataset| p_dataset.last_received.elapsed().as_secs() as usize)
+    }
+
+    /// Fractional seconds since the last message, e.g. for the readsb `seen` field
+    pub fn get_last_seen_secs(&self, plane_id: String) -> Option<f32> {
+        self.plane_db
+            .get(&plane_id)
+            .map(|p_dataset| p_dataset.last_received.elapsed().as_secs_f32())
+    }
+
+    /// Copy of all aircraft data
+    pub fn snapshot(&self) -> PlaneDbSnapshot {
+        PlaneDbSnapshot {
+            plane_db: self.plane_db.clone(),
+        }
+    }
+
+    /// Replace all aircraft data by a snapshot, subscribers are notified of every aircraft
+    pub fn restore(&mut self, snapshot: &PlaneDbSnapshot) {
+        for plane_id in self.plane_db.keys() {
+            if !snapshot.plane_db.contains_key(plane_id) {
+                let _ = self.changes.send(AircraftChange::Removed(plane_id.clone()));
+            }
+        }
+        self.plane_db = snapshot.plane_db.clone();
+        // Ageing continues from now, the snapshot may be old
+        let now = Instant::now();
+        for p_dataset in self.plane_db.values_mut() {
+            p_dataset.last_received = now;
+            p_dataset.last_position = p_dataset.last_position.map(|_| now);
+            p_dataset.age_state = AgeState::Active;
+            p_dataset.motion.restart();
+        }
+        for plane_id in self.plane_db.keys() {
+            let _ = self.changes.send(AircraftChange::Changed(plane_id.clone()));
+        }
+        self.generation += 1;
+    }
+
+    /// Changes on every restore
+    pub fn get_generation(&self) -> u64 {
+        self.generation
+    }
+
+    /// Number of messages stored for an aircraft, including those dropped from the history
+    pub fn get_message_count(&self, plane_id: String) -> usize {
+        self.plane_db
+            .get(&plane_id)
+            .map_or(0, |p_dataset| p_dataset.data_var.messages)
     }
 
     pub fn get_squawk(&self, plane_id: String) -> Option<i32> {
         self.plane_db
             .get(&plane_id)
-            .and_then(|p_dataset| p_dataset.data_var.squawk.last().cloned())
-            .flatten()
+            .and_then(|p_dataset| p_dataset.data_var.latest(|sample| sample.squawk))
     }
 
     pub fn get_ground_speed(&self, plane_id: String) -> Option<f32> {
         self.plane_db
             .get(&plane_id)
-            .and_then(|p_dataset| p_dataset.data_var.ground_speed.last().cloned())
-            .flatten()
+            .and_then(|p_dataset| p_dataset.data_var.latest(|sample| sample.ground_speed))
     }
 
     pub fn get_track(&self, plane_id: String) -> Option<f32> {
         self.plane_db
             .get(&plane_id)
-            .and_then(|p_dataset| p_dataset.data_var.track.last().cloned())
-            .flatten()
+            .and_then(|p_dataset| p_dataset.data_var.latest(|sample| sample.track))
     }
 
     pub fn is_on_ground(&self, plane_id: String) -> Option<bool> {
         self.plane_db
             .get(&plane_id)
-            .and_then(|p_dataset| p_dataset.data_var.is_on_ground.last().cloned())
-            .flatten()
+            .and_then(|p_dataset| p_dataset.data_var.latest(|sample| sample.is_on_ground))
+    }
+
+    /// Returns latest signal level in dBFS, only known for Beast sources
+    pub fn get_signal_level(&self, plane_id: String) -> Option<f32> {
+        self.plane_db
+            .get(&plane_id)
+            .and_then(|p_dataset| p_dataset.data_var.receptions.back())
+            .map(|reception| reception.signal_level)
+    }
+
+    /// Latest known value of every field at `time`, e.g. in a replay
+    pub fn get_sample_at(&self, plane_id: String, time: DateTime<Utc>) -> Option<Sample> {
+        self.plane_db
+            .get(&plane_id)
+            .and_then(|p_dataset| p_dataset.data_var.sample_at(time))
+    }
+
+    /// Samples of an aircraft generated since `since`, oldest first
+    pub fn get_samples_since(&self, plane_id: String, since: DateTime<Utc>) -> Vec<Sample> {
+        self.plane_db
+            .get(&plane_id)
+            .map(|p_dataset| p_dataset.data_var.samples_since(since))
+            .unwrap_or_default()
+    }
+
+    /// Samples of the last `minutes` minutes
+    pub fn get_last_minutes(&self, plane_id: String, minutes: i64) -> Vec<Sample> {
+        self.get_samples_since(plane_id, Utc::now() - chrono::Duration::minutes(minutes))
     }
 
     pub fn remove_plane(&mut self, plane_id: String) {
-        self.plane_db.remove(&plane_id);
+        if self.plane_db.remove(&plane_id).is_some() {
+            let _ = self.changes.send(AircraftChange::Removed(plane_id));
+        }
+    }
+
+    /// Receiver of all changes, lagging receivers lose the oldest changes
+    pub fn subscribe_changes(&self) -> broadcast::Receiver<AircraftChange> {
+        self.changes.subscribe()
+    }
+
+    /// Current state of an aircraft, `None` if unknown or removed
+    pub fn get_aircraft_state(&self, plane_id: String) -> Option<AircraftState> {
+        let p_dataset = self.plane_db.get(&plane_id)?;
+        let position = self.get_latest_known_pos_any(plane_id.clone());
+        Some(AircraftState {
+            hex_ident: plane_id.clone(),
+            call_sign: self.get_call_sign(plane_id.clone()),
+            latitude: position.map(|pos| pos.0),
+            longitude: position.map(|pos| pos.1),
+            altitude: position
+                .map(|pos| pos.2)
+                .or_else(|| self.get_latest_known_altitude(plane_id.clone())),
+            ground_speed: self.get_ground_speed(plane_id.clone()),
+            track: self.get_track(plane_id.clone()),
+            vertical_rate: self.get_vertical_rate(plane_id.clone()),
+            squawk: self.get_squawk(plane_id.clone()),
+            is_on_ground: self.is_on_ground(plane_id),
+            position_source: position.map(|pos| pos.3),
+            last_seen: p_dataset.last_received.elapsed().as_secs() as usize,
+            age_state: p_dataset.age_state,
+            motion: p_dataset.motion.state(),
+            status: p_dataset.status,
+            receivers: p_dataset.receivers.len(),
+        })
+    }
+
+    /// Positions of an aircraft in order of reception, e.g. for exports
+    ///
+    /// Altitude, speed, track, vertical rate and squawk are the latest known at that time.
+    pub fn get_track_points(&self, plane_id: String) -> Vec<TrackPoint> {
+        let Some(p_dataset) = self.plane_db.get(&plane_id) else {
+            return vec![];
+        };
+        let mut points = vec![];
+        let mut latest = Sample::default();
+        for sample in p_dataset.data_var.samples.iter() {
+            latest.merge(sample);
+            if let (Some(latitude), Some(longitude)) = (sample.latitude, sample.longitude) {
+                points.push(TrackPoint {
+                    time: Some(sample.time),
+                    latitude,
+                    longitude,
+                    altitude: latest.altitude,
+                    ground_speed: latest.ground_speed,
+                    track: latest.track,
+                    vertical_rate: latest.vertical_rate,
+                    squawk: latest.squawk,
+                    source: sample.source.clone(),
+                });
+            }
+        }
+        points
+    }
+
+    /// Current state of all aircraft
+    pub fn get_aircraft_states(&self) -> Vec<AircraftState> {
+        self.plane_db
+            .keys()
+            .filter_map(|plane_id| self.get_aircraft_state(plane_id.clone()))
+            .collect()
     }
 
     // pub fn get_latest_pos(&self, plane_id: String) -> Option<(f32, f32, f32)> {
@@ -112,41 +637,40 @@ impl SharedDataDb {
 
     // Return last not None except empty
     pub fn get_latest_known_pos(&self, plane_id: String) -> Option<(f32, f32, f32)> {
-        // let last_some = vec.iter().rev().find_map(|x| *x);
-        self.plane_db.get(&plane_id).and_then(|p_dataset| {
-            let lat = p_dataset
-                .data_var
-                .latitude
-                .iter()
-                .rev()
-                .find_map(|lat| *lat);
-            let long = p_dataset
-                .data_var
-                .longitude
-                .iter()
-                .rev()
-                .find_map(|long| *long);
-            let alt = p_dataset
-                .data_var
-                .altitude
-                .iter()
-                .rev()
-                .find_map(|alt| *alt);
-            lat.and_then(|lat| long.and_then(|long| alt.map(|alt| (lat, long, alt))))
-        })
+        let data_var = &self.plane_db.get(&plane_id)?.data_var;
+        let (lat, long) = data_var.latest(|sample| sample.latitude.zip(sample.longitude))?;
+        let alt = data_var.latest(|sample| sample.altitude)?;
+        Some((lat, long, alt))
+    }
+
+    /// Returns latest position derived from multilateration, altitude falls back to the latest known
+    pub fn get_latest_mlat_pos(&self, plane_id: String) -> Option<(f32, f32, f32)> {
+        let mlat = *self.plane_db.get(&plane_id)?.data_var.mlat.back()?;
+        let alt = mlat
+            .altitude
+            .or_else(|| self.get_latest_known_altitude(plane_id))?;
+        Some((mlat.latitude, mlat.longitude, alt))
+    }
+
+    /// Returns latest known position, ADS-B or multilateration, whichever has been received last
+    pub fn get_latest_known_pos_any(&self, plane_id: String) -> Option<(f32, f32, f32, PositionSource)> {
+        let data_var = &self.plane_db.get(&plane_id)?.data_var;
+        let adsb_time = data_var.latest(|sample| sample.latitude.and(sample.longitude).map(|_| sample.time));
+        let mlat_time = data_var.mlat.back().map(|mlat| mlat.time);
+        let adsb = self
+            .get_latest_known_pos(plane_id.clone())
+            .map(|pos| (pos.0, pos.1, pos.2, PositionSource::Adsb));
+        let mlat = self
+            .get_latest_mlat_pos(plane_id)
+            .map(|pos| (pos.0, pos.1, pos.2, PositionSource::Mlat));
+        if mlat_time > adsb_time { mlat.or(adsb) } else { adsb.or(mlat) }
     }
 
     /// Returns latest known altitude
     pub fn get_latest_known_altitude(&self, plane_id: String) -> Option<f32> {
-        self.plane_db.get(&plane_id).and_then(|p_dataset| {
-            let alt = p_dataset
-                .data_var
-                .altitude
-                .iter()
-                .rev()
-                .find_map(|alt| *alt);
-            alt
-        })
+        self.plane_db
+            .get(&plane_id)
+            .and_then(|p_dataset| p_dataset.data_var.latest(|sample| sample.altitude))
     }
 
     pub fn get_call_sign(&self, plane_id: String) -> Option<String> {
@@ -158,8 +682,7 @@ impl SharedDataDb {
     pub fn get_vertical_rate(&self, plane_id: String) -> Option<f32> {
         self.plane_db
             .get(&plane_id)
-            .and_then(|p_dataset| p_dataset.data_var.vertical_rate.last().cloned())
-            .flatten()
+            .and_then(|p_dataset| p_dataset.data_var.latest(|sample| sample.vertical_rate))
     }
 
     pub fn get_plane_distance_to_lat_lon(
@@ -178,6 +701,8 @@ impl SharedDataDb {
 
     pub fn update_data(
         &mut self,
+        source: &str,
+        received: DateTime<Utc>,
         session_id: Option<String>,
         aircraft_id: Option<String>,
         hex_ident: String,
@@ -201,46 +726,151 @@ impl SharedDataDb {
         spi: Option<bool>,
         is_on_ground: Option<bool>,
     ) {
-        let temp = &mut self.plane_db;
-        // Update if plane already created
-        if temp.contains_key(&hex_ident) {
-            // TODO: implement update
-            let data_temp = temp.get_mut(&hex_ident).unwrap();
-            // TODO: Define message types, 3 = ES Airborne Position Message
-            // TODO: Clean up, do not differentiate between types
-            // New data, so last_seen is 0 = now
-            data_temp.last_seen = 0;
-            data_temp.data_var.transmission_type.push(transmission_type);
-            data_temp.data_var.altitude.push(altitude);
-            data_temp.data_var.latitude.push(latitude);
-            data_temp.data_var.longitude.push(longitude);
-
-            if transmission_type == 4 {
-                data_temp.data_var.vertical_rate.push(vertical_rate);
-                data_temp.data_var.ground_speed.push(ground_speed);
-                data_temp.data_var.track.push(track);
+        // Same transmission received via another source, only the reception is recorded
+        let fingerprint = message_fingerprint(
+            &hex_ident,
+            transmission_type,
+            &call_sign,
+            [altitude, ground_speed, track, latitude, longitude, vertical_rate],
+            squawk,
+            [alert, emergency, spi, is_on_ground],
+        );
+        let counters = self.source_counters.entry(source.to_string()).or_default();
+        if let Some(data_temp) = self.plane_db.get_mut(&hex_ident) {
+            let now = Instant::now();
+            while data_temp
+                .recent
+                .front()
+                .is_some_and(|recent| now.duration_since(recent.0) > DUPLICATE_WINDOW)
+            {
+                data_temp.recent.pop_front();
             }
-            if transmission_type == 5 {
-                if call_sign.is_some() {
-                    if call_sign.clone().unwrap().len() > 0 {
-                        data_temp.data_const.call_sign = call_sign;
-                    }
+            let duplicate = data_temp
+                .recent
+                .iter()
+                .any(|recent| recent.1 == fingerprint && recent.2 != source);
+
+            let reception = data_temp
+                .receivers
+                .entry(source.to_string())
+                .or_insert_with(SourceReception::new);
+            reception.last_heard = Utc::now();
+            if duplicate {
+                reception.counters.duplicates += 1;
+                counters.duplicates += 1;
+                data_temp.last_received = now;
+                if latitude.is_some() && longitude.is_some() {
+                    data_temp.last_position = Some(now);
                 }
+                return;
             }
-            if transmission_type == 6 {
-                if squawk.is_some() {
-                    data_temp.data_var.squawk.push(squawk);
-                }
+            reception.counters.messages += 1;
+            data_temp.recent.push_back((now, fingerprint, source.to_string()));
+        }
+        counters.messages += 1;
+
+        // Implausible positions and altitudes are removed from the message, it is counted and flagged
+        let time = generated_date.and_time(generated_time).and_utc();
+        let mut plausibility_state = self
+            .plane_db
+            .get(&hex_ident)
+            .map(|p_dataset| p_dataset.plausibility)
+            .unwrap_or_default();
+        let (mut latitude, mut longitude, mut altitude) = (latitude, longitude, altitude);
+        let mut rejected = None;
+        if let (Some(lat), Some(lon)) = (latitude, longitude)
+            && let Err(rejection) = self.plausibility.check_position(&mut plausibility_state, time, lat, lon, self.receiver)
+        {
+            (latitude, longitude) = (None, None);
+            rejected = Some(rejection);
+        }
+        if let Some(alt) = altitude
+            && let Err(rejection) = self.plausibility.check_altitude(&mut plausibility_state, time, alt)
+        {
+            altitude = None;
+            rejected = rejected.or(Some(rejection));
+        }
+        if rejected.is_some() {
+            counters.rejected += 1;
+        }
+
+        // Re-serve the merged feed, only encoded if somebody is listening
+        if self.sbs_output.receiver_count() > 0 {
+            let message = SbsMessage {
+                message_type: message_type.clone(),
+                transmission_type: transmission_type as u8,
+                session_id: session_id.clone(),
+                aircraft_id: aircraft_id.clone(),
+                hex_ident: hex_ident.clone(),
+                flight_id: flight_id.clone(),
+                generated_date,
+                generated_time,
+                logged_date,
+                logged_time,
+                call_sign: call_sign.clone(),
+                altitude,
+                ground_speed,
+                track,
+                latitude,
+                longitude,
+                vertical_rate,
+                squawk,
+                alert,
+                emergency,
+                spi,
+                is_on_ground,
+            };
+            let _ = self.sbs_output.send(message.to_line());
+        }
+
+        // Notify subscribers after the update, repeated messages do not change the state
+        let plane_id = hex_ident.clone();
+        let before = self.get_comparable_state(plane_id.clone());
+
+        let sample = Sample {
+            time,
+            source: source.to_string(),
+            transmission_type,
+            altitude,
+            ground_speed,
+            track,
+            latitude,
+            longitude,
+            vertical_rate,
+            squawk,
+            alert,
+            emergency,
+            spi,
+            is_on_ground,
+            rejected,
+        };
+
+        let history = self.history;
+        let temp = &mut self.plane_db;
+        // Update if plane already created
+        if let Some(data_temp) = temp.get_mut(&hex_ident) {
+            // New data
+            data_temp.last_received = Instant::now();
+            if latitude.is_some() && longitude.is_some() {
+                data_temp.last_position = Some(Instant::now());
             }
-            if transmission_type == 7 {
-                if is_on_ground.is_some() {
-                    data_temp.data_var.is_on_ground.push(is_on_ground);
-                }
+            update_motion(&mut data_temp.motion, received, &sample);
+            data_temp.data_var.push(sample, &history);
+            data_temp.plausibility = plausibility_state;
+            if rejected.is_some() {
+                data_temp
+                    .receivers
+                    .entry(source.to_string())
+                    .or_insert_with(SourceReception::new)
+                    .counters
+                    .rejected += 1;
             }
-            if transmission_type == 8 {
-                if is_on_ground.is_some() {
-                    data_temp.data_var.is_on_ground.push(is_on_ground);
-                }
+
+            // 1 = ES Identification, 5 = Surveillance Alt Message
+            if (transmission_type == 1 || transmission_type == 5)
+                && call_sign.as_ref().is_some_and(|call_sign| !call_sign.is_empty())
+            {
+                data_temp.data_const.call_sign = call_sign;
             }
         } else {
             // New aircraft, fetch additional data from cache or online
@@ -256,12 +886,33 @@ impl SharedDataDb {
                 }
             });
 
-            //
+            let mut motion = MotionFilter::default();
+            update_motion(&mut motion, received, &sample);
+            let mut data_var = PlaneDataVar::default();
+            data_var.push(sample, &history);
             temp.insert(
                 hex_ident.clone(),
                 PlaneDataSet {
                     plane_id: hex_ident.clone(),
-                    last_seen: 0, // New insert, so last_seen is now
+                    last_received: Instant::now(),
+                    last_position: (latitude.is_some() && longitude.is_some()).then(Instant::now),
+                    age_state: AgeState::Active,
+                    status: None,
+                    events: VecDeque::new(),
+                    receivers: HashMap::from([(
+                        source.to_string(),
+                        SourceReception {
+                            counters: SourceCounters {
+                                messages: 1,
+                                duplicates: 0,
+                                rejected: rejected.is_some() as usize,
+                            },
+                            ..SourceReception::new()
+                        },
+                    )]),
+                    recent: VecDeque::from([(Instant::now(), fingerprint, source.to_string())]),
+                    plausibility: plausibility_state,
+                    motion,
                     data_const: PlaneDataConst {
                         session_id,
                         aircraft_id,
@@ -269,32 +920,388 @@ impl SharedDataDb {
                         flight_id,
                         call_sign,
                     },
-                    data_var: PlaneDataVar {
-                        message_type: vec![message_type],
-                        transmission_type: vec![transmission_type],
-                        generated_date: vec![generated_date],
-                        generated_time: vec![generated_time],
-                        logged_date: vec![logged_date],
-                        logged_time: vec![logged_time],
-                        altitude: vec![altitude],
-                        ground_speed: vec![ground_speed],
-                        track: vec![track],
-                        latitude: vec![latitude],
-                        longitude: vec![longitude],
-                        vertical_rate: vec![vertical_rate],
-                        squawk: vec![squawk],
-                        alert: vec![alert],
-                        emergency: vec![emergency],
-                        spi: vec![spi],
-                        is_on_ground: vec![is_on_ground],
-                    },
+                    data_var,
                 },
             );
         }
+        if before.is_none() {
+            let _ = self.changes.send(AircraftChange::New(plane_id));
+        } else if before != self.get_comparable_state(plane_id.clone()) {
+            let _ = self.changes.send(AircraftChange::Changed(plane_id));
+        }
+    }
+
+    /// State without the fields that change with every message, see `update_data`
+    fn get_comparable_state(&self, plane_id: String) -> Option<AircraftState> {
+        self.get_aircraft_state(plane_id).map(|state| AircraftState {
+            last_seen: 0,
+            motion: None,
+            ..state
+        })
+    }
+
+    /// Sources that heard an aircraft, sorted by label
+    pub fn get_receivers(&self, plane_id: String) -> Vec<(String, SourceReception)> {
+        let mut receivers: Vec<(String, SourceReception)> = self
+            .plane_db
+            .get(&plane_id)
+            .map(|p_dataset| {
+                p_dataset
+                    .receivers
+                    .iter()
+                    .map(|(source, reception)| (source.clone(), reception.clone()))
+                    .collect()
+            })
+            .unwrap_or_default();
+        receivers.sort_by(|a, b| a.0.cmp(&b.0));
+        receivers
+    }
+
+    /// Message counters of all sources, sorted by label
+    pub fn get_source_counters(&self) -> Vec<(String, SourceCounters)> {
+        let mut counters: Vec<(String, SourceCounters)> = self
+            .source_counters
+            .iter()
+            .map(|(source, counters)| (source.clone(), *counters))
+            .collect();
+        counters.sort_by(|a, b| a.0.cmp(&b.0));
+        counters
+    }
+
+    /// Sender of every accepted message as SBS line, see `update_data`, clients subscribe to it
+    pub fn get_sbs_output(&self) -> broadcast::Sender<String> {
+        self.sbs_output.clone()
+    }
+
+    /// Current state of all aircraft as SBS messages, identification, position, velocity and squawk
+    pub fn get_sbs_snapshot(&self) -> Vec<SbsMessage> {
+        let now = Utc::now();
+        let mut messages = vec![];
+        for (plane_id, p_dataset) in self.plane_db.iter() {
+            let message = |transmission_type: u8| SbsMessage {
+                message_type: "MSG".to_string(),
+                transmission_type,
+                session_id: Some("1".to_string()),
+                aircraft_id: Some("1".to_string()),
+                hex_ident: plane_id.clone(),
+                flight_id: Some("1".to_string()),
+                generated_date: now.date_naive(),
+                generated_time: now.time(),
+                logged_date: now.date_naive(),
+                logged_time: now.time(),
+                call_sign: None,
+                altitude: None,
+                ground_speed: None,
+                track: None,
+                latitude: None,
+                longitude: None,
+                vertical_rate: None,
+                squawk: None,
+                alert: None,
+                emergency: None,
+                spi: None,
+                is_on_ground: None,
+            };
+
+            if let Some(call_sign) = self.get_call_sign(plane_id.clone()) {
+                messages.push(SbsMessage {
+                    call_sign: Some(call_sign),
+                    ..message(1)
+                });
+            }
+            if let Some(pos) = self.get_latest_known_pos_any(plane_id.clone()) {
+                messages.push(SbsMessage {
+                    altitude: Some(pos.2),
+                    latitude: Some(pos.0),
+                    longitude: Some(pos.1),
+                    is_on_ground: self.is_on_ground(plane_id.clone()),
+                    ..message(3)
+                });
+            }
+            let ground_speed = self.get_ground_speed(plane_id.clone());
+            if ground_speed.is_some() {
+                messages.push(SbsMessage {
+                    ground_speed,
+                    track: self.get_track(plane_id.clone()),
+                    vertical_rate: self.get_vertical_rate(plane_id.clone()),
+                    ..message(4)
+                });
+            }
+            if let Some(squawk) = self.get_squawk(plane_id.clone()) {
+                messages.push(SbsMessage {
+                    squawk: Some(squawk),
+                    alert: p_dataset.data_var.latest(|sample| sample.alert),
+                    emergency: p_dataset.data_var.latest(|sample| sample.emergency),
+                    spi: p_dataset.data_var.latest(|sample| sample.spi),
+                    ..message(6)
+                });
+            }
+        }
+        messages
+    }
+
+    /// Store a lifecycle event of a BaseStation record, the aircraft has to exist.
+    /// A removed or deleted aircraft (STA RM or AD) is removed here as well.
+    pub fn add_event(&mut self, hex_ident: String, event: AircraftEvent) {
+        if matches!(event, AircraftEvent::StatusChanged(SbsStatus::Removed | SbsStatus::Deleted)) {
+            self.remove_plane(hex_ident);
+            return;
+        }
+        if let Some(data_temp) = self.plane_db.get_mut(&hex_ident) {
+            match &event {
+                AircraftEvent::CallSignChanged(call_sign) if data_temp.data_const.call_sign.as_ref() != Some(call_sign) => {
+                    data_temp.data_const.call_sign = Some(call_sign.clone());
+                    let _ = self.changes.send(AircraftChange::Changed(hex_ident.clone()));
+                }
+                AircraftEvent::StatusChanged(status) if data_temp.status != Some(*status) => {
+                    data_temp.status = Some(*status);
+                    let _ = self.changes.send(AircraftChange::Changed(hex_ident.clone()));
+                }
+                _ => {}
+            }
+            if data_temp.events.len() >= MAX_EVENTS {
+                data_temp.events.pop_front();
+            }
+            data_temp.events.push_back((Utc::now(), event));
+        }
+    }
+
+    /// Latest sample with an implausible position or altitude, within the history
+    pub fn get_last_rejection(&self, plane_id: String) -> Option<(DateTime<Utc>, Rejection)> {
+        self.plane_db.get(&plane_id).and_then(|p_dataset| {
+            p_dataset
+                .data_var
+                .samples
+                .iter()
+                .rev()
+                .find_map(|sample| sample.rejected.map(|rejection| (sample.time, rejection)))
+        })
+    }
+
+    /// Latest `count` lifecycle events, newest first
+    pub fn get_events(&self, plane_id: String, count: usize) -> Vec<(DateTime<Utc>, AircraftEvent)> {
+        self.plane_db
+            .get(&plane_id)
+            .map(|p_dataset| p_dataset.events.iter().rev().take(count).cloned().collect())
+            .unwrap_or_default()
+    }
+
+    /// Heartbeat (CLK record) of a source, keeps it alive even without traffic
+    pub fn update_heartbeat(&mut self, source: &str) {
+        self.source_heartbeat.insert(source.to_string(), Utc::now());
+    }
+
+    /// Returns seconds since the last heartbeat per source, sorted by source
+    pub fn get_heartbeats(&self) -> Vec<(String, i64)> {
+        let now = Utc::now();
+        let mut heartbeats: Vec<(String, i64)> = self
+            .source_heartbeat
+            .iter()
+            .map(|(source, time)| (source.clone(), (now - *time).num_seconds()))
+            .collect();
+        heartbeats.sort();
+        heartbeats
+    }
+
+    /// Store a position derived from multilateration, kept apart from ADS-B positions
+    ///
+    /// Implausible positions and altitudes are counted as rejected like in `update_data` and not stored.
+    pub fn update_mlat_position(
+        &mut self, source: &str, hex_ident: String, time: DateTime<Utc>, latitude: f32, longitude: f32, altitude: Option<f32>,
+    ) {
+        if let Some(data_temp) = self.plane_db.get_mut(&hex_ident) {
+            data_temp.last_received = Instant::now();
+            let reception = data_temp
+                .receivers
+                .entry(source.to_string())
+                .or_insert_with(SourceReception::new);
+            reception.last_heard = Utc::now();
+
+            let position_plausible = self
+                .plausibility
+                .check_position(&mut data_temp.plausibility, time, latitude, longitude, self.receiver)
+                .is_ok();
+            let altitude_plausible = altitude.is_none_or(|alt| {
+                self.plausibility.check_altitude(&mut data_temp.plausibility, time, alt).is_ok()
+            });
+            if !position_plausible || !altitude_plausible {
+                reception.counters.rejected += 1;
+                self.source_counters.entry(source.to_string()).or_default().rejected += 1;
+            }
+            if !position_plausible {
+                return;
+            }
+            let altitude = altitude.filter(|_| altitude_plausible);
+
+            data_temp.last_position = Some(Instant::now());
+            let mlat = MlatSample {
+                time,
+                latitude,
+                longitude,
+                altitude,
+            };
+            data_temp.motion.update_position(mlat.time, latitude, longitude, PositionAccuracy::Mlat);
+            push_bounded(&mut data_temp.data_var.mlat, mlat, &self.history, |mlat| mlat.time);
+            let _ = self.changes.send(AircraftChange::Changed(hex_ident));
+        }
+    }
+
+    /// Store receiver timestamp and signal level of a Beast frame
+    pub fn update_reception(
+        &mut self, source: &str, hex_ident: String, time: DateTime<Utc>, beast_timestamp: u64, signal_level: f32,
+    ) {
+        if let Some(data_temp) = self.plane_db.get_mut(&hex_ident) {
+            data_temp.last_received = Instant::now();
+            let reception = data_temp
+                .receivers
+                .entry(source.to_string())
+                .or_insert_with(SourceReception::new);
+            reception.last_heard = Utc::now();
+            if reception.signal_level != Some(signal_level) {
+                reception.signal_level = Some(signal_level);
+                let _ = self.changes.send(AircraftChange::Changed(hex_ident.clone()));
+            }
+            let beast_reception = BeastReception {
+                time,
+                beast_timestamp,
+                signal_level,
+            };
+            push_bounded(&mut data_temp.data_var.receptions, beast_reception, &self.history, |reception| reception.time);
+        }
+    }
+}
+
+/// Update the age states of all aircraft and remove expired ones, runs without Bevy as well
+pub async fn expire_planes(data_share: &Arc<Mutex<SharedDataDb>>, expiry: Expiry) {
+    let mut interval = tokio::time::interval(EXPIRY_INTERVAL);
+    interval.tick().await; // Completes immediately
+    loop {
+        interval.tick().await;
+        let mut data_tmp = data_share.lock().unwrap();
+        data_tmp.update_age_states(&expiry);
+        data_tmp.expire_planes(Duration::from_secs(expiry.identity_timeout_secs));
     }
 }
 
+/// Feed the accepted values of a message to the motion filter, timed by their reception like MLAT
+/// results, as the generated time depends on the clock of the source
+fn update_motion(motion: &mut MotionFilter, received: DateTime<Utc>, sample: &Sample) {
+    if let (Some(latitude), Some(longitude)) = (sample.latitude, sample.longitude) {
+        motion.update_position(received, latitude, longitude, PositionAccuracy::Adsb);
+    }
+    if let Some(altitude) = sample.altitude {
+        motion.update_altitude(received, altitude);
+    }
+    if let (Some(ground_speed), Some(track)) = (sample.ground_speed, sample.track) {
+        motion.update_velocity(received, ground_speed, track);
+    }
+    if let Some(vertical_rate) = sample.vertical_rate {
+        motion.update_vertical_rate(received, vertical_rate);
+    }
+}
+
+/// Hash of the content of a message, equal for the same transmission received by several sources
+fn message_fingerprint(
+    hex_ident: &str,
+    transmission_type: usize,
+    call_sign: &Option<String>,
+    values: [Option<f32>; 6],
+    squawk: Option<i32>,
+    flags: [Option<bool>; 4],
+) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    hex_ident.hash(&mut hasher);
+    transmission_type.hash(&mut hasher);
+    call_sign.hash(&mut hasher);
+    for value in values {
+        value.map(f32::to_bits).hash(&mut hasher);
+    }
+    squawk.hash(&mut hasher);
+    flags.hash(&mut hasher);
+    hasher.finish()
+}
+
 // Store additional aircraft data in here
 // Hexdb.io -> kv (file cache) -> AIRCRAFT_ADD_DATA
 pub static AIRCRAFT_ADD_DATA: Lazy<Mutex<HashMap<String, Aircraft>>> =
     Lazy::new(|| Mutex::new(HashMap::new()));
+
+// Counters of every UDP sender, the feeders pushing data to luftraum
+pub struct UdpSenderStats {
+    pub listener: String,          // Label of the UDP listener
+    pub datagrams: usize,          // Number of received datagrams
+    pub bytes: usize,              // Number of received bytes
+    pub dropped: usize,            // Number of messages that could not be decoded
+    pub last_seen: DateTime<Utc>,  // Time of the last datagram
+}
+
+pub static UDP_SENDERS: Lazy<Mutex<HashMap<SocketAddr, UdpSenderStats>>> =
+    Lazy::new(|| Mutex::new(HashMap::new()));
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sample(seconds: i64, altitude: Option<f32>, track: Option<f32>) -> Sample {
+        Sample {
+            time: DateTime::from_timestamp(1700000000 + seconds, 0).unwrap(),
+            altitude,
+            track,
+            ..Sample::default()
+        }
+    }
+
+    #[test]
+    fn test_history() {
+        let history = History { depth: 3, window_minutes: 1 };
+        let mut data_var = PlaneDataVar::default();
+        data_var.push(sample(0, Some(1000.0), None), &history);
+        data_var.push(sample(10, None, Some(90.0)), &history);
+        data_var.push(sample(20, Some(1200.0), None), &history);
+
+        // Latest known value of every field at that time
+        let at = data_var.sample_at(DateTime::from_timestamp(1700000015, 0).unwrap()).unwrap();
+        assert_eq!((at.altitude, at.track), (Some(1000.0), Some(90.0)));
+        assert_eq!(data_var.sample_at(DateTime::from_timestamp(1699999999, 0).unwrap()), None);
+        assert_eq!(data_var.latest(|sample| sample.altitude), Some(1200.0));
+
+        // Bounded by depth
+        data_var.push(sample(30, None, None), &history);
+        assert_eq!(data_var.samples.len(), 3);
+        assert_eq!(data_var.samples_since(DateTime::from_timestamp(1700000020, 0).unwrap()).len(), 2);
+
+        // Bounded by the time window
+        data_var.push(sample(85, None, None), &history);
+        assert_eq!(data_var.samples.len(), 2);
+        assert_eq!(data_var.messages, 5);
+    }
+
+    #[test]
+    fn test_history_out_of_order() {
+        let history = History { depth: 10, window_minutes: 1 };
+        let mut data_var = PlaneDataVar::default();
+        data_var.push(sample(0, Some(1000.0), None), &history);
+        data_var.push(sample(20, Some(1200.0), None), &history);
+        // Late sample of a source timed by its sender
+        data_var.push(sample(10, Some(1100.0), Some(90.0)), &history);
+
+        let times: Vec<i64> = data_var.samples.iter().map(|sample| sample.time.timestamp() - 1700000000).collect();
+        assert_eq!(times, vec![0, 10, 20]);
+        assert_eq!(data_var.latest(|sample| sample.altitude), Some(1200.0));
+
+        // An old sample doesn't move the time window
+        data_var.push(sample(-100, Some(900.0), None), &history);
+        assert_eq!(data_var.samples.len(), 3);
+    }
+
+    #[test]
+    fn test_age_state() {
+        let expiry = Expiry { position_timeout_secs: 15, lost_timeout_secs: 30, identity_timeout_secs: 60 };
+        let secs = Duration::from_secs;
+        assert_eq!(expiry.age_state(secs(1), Some(secs(1))), AgeState::Active);
+        // Still receiving, but no recent position
+        assert_eq!(expiry.age_state(secs(1), Some(secs(20))), AgeState::Stale);
+        assert_eq!(expiry.age_state(secs(20), None), AgeState::Stale);
+        assert_eq!(expiry.age_state(secs(30), Some(secs(30))), AgeState::Lost);
+    }
+}
@@ -1,76 +1,601 @@
 use std::collections::HashMap;
-use chrono::{NaiveDate, NaiveTime};
+use chrono::{DateTime, Utc};
 use std::sync::{Arc, Mutex};
 use once_cell::sync::Lazy;
+use crate::adsb::{all_call_address, hex_to_bytes, AdsbDecoder, AdsbError, AdsbMessage};
+use crate::aircraft_json::{AircraftJson, JsonAircraft};
+use crate::avr::parse_avr;
+use crate::beast::{BeastDecoder, BeastFrame, BeastFrameType};
+use serde::Deserialize;
 use crate::data_share::*;
 use crate::hex_lookup::Aircraft;
+use crate::sbs::{SbsParseError, SbsRecord};
 
 
 
-pub fn decode_message_sbs(data_share: &Arc<Mutex<SharedDataDb>>, message: String) {
-    if message.len() > 0 && message.len() < 255 && message.is_ascii() { // Basic checks ;-)
-
-        // Split message into (22) pieces by definition of SBS messages
-        let vec: Vec<&str> = message.split(',').collect();
-
-        if vec.len() == 22 {
-            // Map each field to needed data type
-            // TODO: More sophisticated decoding, crypt messages on transport
-            let tmp_transmission_type = vec[1].parse::<usize>().ok().unwrap();
-            let tmp_generated_date = NaiveDate::parse_from_str(vec[6], "%Y/%m/%d").ok().unwrap();
-            let tmp_generated_time = NaiveTime::parse_from_str(vec[7], "%H:%M:%S%.f")
-                .ok()
-                .unwrap();
-            let tmp_logged_date = NaiveDate::parse_from_str(vec[8], "%Y/%m/%d").ok().unwrap();
-            let tmp_logged_time = NaiveTime::parse_from_str(vec[9], "%H:%M:%S%.f")
-                .ok()
-                .unwrap();
-            let tmp_altitude = vec[11].parse::<f32>().ok();
-            let tmp_ground_speed = vec[12].parse::<f32>().ok();
-            let tmp_track = vec[13].parse::<f32>().ok();
-            let tmp_latitude = vec[14].parse::<f32>().ok();
-            let tmp_longitude = vec[15].parse::<f32>().ok();
-            let tmp_vertical_rate = vec[16].parse::<f32>().ok();
-            let tmp_squawk = vec[17].parse::<i32>().ok();
-            let tmp_alert = vec[18].parse::<usize>().unwrap_or(0);
-            let tmp_alert_bool = matches!(tmp_alert, 1);
-            let tmp_emergency = vec[19].parse::<usize>().unwrap_or(0);
-            let tmp_emergency_bool = matches!(tmp_emergency, 1);
-            let tmp_spi = vec[20].parse::<usize>().unwrap_or(0);
-            let tmp_spi_bool = matches!(tmp_spi, 1);
-            let tmp_is_on_ground = vec[21].parse::<usize>().unwrap_or(0);
-            let tmp_is_on_ground_bool = matches!(tmp_is_on_ground, 1);
-
-            // Write data to 'global' struct
-            let mut data_tmp = data_share.lock().unwrap();
-            data_tmp.update_data(
-                Some(vec[2].to_string()),  // SessionID
-                Some(vec[3].to_string()),  // AircraftID
-                vec[4].to_string(),        // HexIdent
-                Some(vec[5].to_string()),  // FlightID
-                Some(vec[10].to_string()), // CallSign
-                vec[0].to_string(),        // MessageType
-                tmp_transmission_type,
-                tmp_generated_date,
-                tmp_generated_time,
-                tmp_logged_date,
-                tmp_logged_time,
-                tmp_altitude,
-                tmp_ground_speed,
-                tmp_track,
-                tmp_latitude,
-                tmp_longitude,
-                tmp_vertical_rate,
-                tmp_squawk,
-                Some(tmp_alert_bool),
-                Some(tmp_emergency_bool),
-                Some(tmp_spi_bool),
-                Some(tmp_is_on_ground_bool),
+/// Decode one SBS line of `source` and store it, failures are counted by reason
+pub fn decode_message_sbs(
+    data_share: &Arc<Mutex<SharedDataDb>>,
+    source: &str,
+    message: String,
+    received: Option<DateTime<Utc>>,
+) -> Result<(), SbsParseError> {
+    let record = match SbsRecord::parse(&message) {
+        Ok(record) => record,
+        Err(e) => {
+            // Dropped messages, add to statistics for RCA
+            data_share.lock().unwrap().count_dropped(&format!("sbs: {}", e.reason()));
+            return Err(e);
+        }
+    };
+
+    let sbs = match record {
+        SbsRecord::Msg(sbs) => sbs,
+        SbsRecord::Clk { .. } => {
+            data_share.lock().unwrap().update_heartbeat(source);
+            return Ok(());
+        }
+        SbsRecord::Air { hex_ident } => {
+            // Create the aircraft like an all call reply would
+            store_plane_update(
+                data_share,
+                source,
+                PlaneUpdate {
+                    hex_ident: hex_ident.clone(),
+                    transmission_type: 8,
+                    time: received,
+                    ..Default::default()
+                },
             );
+            data_share.lock().unwrap().add_event(hex_ident, AircraftEvent::Appeared);
+            return Ok(());
+        }
+        SbsRecord::Id { hex_ident, call_sign } => {
+            if let Some(call_sign) = call_sign {
+                data_share
+                    .lock()
+                    .unwrap()
+                    .add_event(hex_ident, AircraftEvent::CallSignChanged(call_sign));
+            }
+            return Ok(());
+        }
+        SbsRecord::Sel { hex_ident, .. } => {
+            data_share.lock().unwrap().add_event(hex_ident, AircraftEvent::Selected);
+            return Ok(());
+        }
+        SbsRecord::Sta { hex_ident, status } => {
+            data_share
+                .lock()
+                .unwrap()
+                .add_event(hex_ident, AircraftEvent::StatusChanged(status));
+            return Ok(());
+        }
+    };
+
+    // Write data to 'global' struct
+    let mut data_tmp = data_share.lock().unwrap();
+    data_tmp.update_data(
+        source,
+        received.unwrap_or_else(Utc::now),
+        sbs.session_id,
+        sbs.aircraft_id,
+        sbs.hex_ident,
+        sbs.flight_id,
+        sbs.call_sign,
+        sbs.message_type,
+        sbs.transmission_type as usize,
+        sbs.generated_date,
+        sbs.generated_time,
+        sbs.logged_date,
+        sbs.logged_time,
+        sbs.altitude,
+        sbs.ground_speed,
+        sbs.track,
+        sbs.latitude,
+        sbs.longitude,
+        sbs.vertical_rate,
+        sbs.squawk,
+        sbs.alert,
+        sbs.emergency,
+        sbs.spi,
+        sbs.is_on_ground,
+    );
+    Ok(())
+}
+
+/// Decoded data of one message in SBS terms, see `SharedDataDb::update_data`
+#[derive(Debug, Default)]
+pub struct PlaneUpdate {
+    pub hex_ident: String,
+    pub transmission_type: usize,
+    pub call_sign: Option<String>,
+    pub altitude: Option<f32>,
+    pub ground_speed: Option<f32>,
+    pub track: Option<f32>,
+    pub latitude: Option<f32>,
+    pub longitude: Option<f32>,
+    pub vertical_rate: Option<f32>,
+    pub squawk: Option<i32>,
+    pub alert: Option<bool>,
+    pub emergency: Option<bool>,
+    pub spi: Option<bool>,
+    pub is_on_ground: Option<bool>,
+    pub time: Option<DateTime<Utc>>, // Time of reception, now if not set, e.g. recording time of a replay
+}
+
+/// Write an update of a non-SBS source to the 'global' struct
+pub fn store_plane_update(data_share: &Arc<Mutex<SharedDataDb>>, source: &str, update: PlaneUpdate) {
+    let now = update.time.unwrap_or_else(Utc::now);
+    let mut data_tmp = data_share.lock().unwrap();
+    data_tmp.update_data(
+        source,
+        now,
+        None,
+        None,
+        update.hex_ident,
+        None,
+        update.call_sign,
+        "MSG".to_string(),
+        update.transmission_type,
+        now.date_naive(),
+        now.time(),
+        now.date_naive(),
+        now.time(),
+        update.altitude,
+        update.ground_speed,
+        update.track,
+        update.latitude,
+        update.longitude,
+        update.vertical_rate,
+        update.squawk,
+        update.alert,
+        update.emergency,
+        update.spi,
+        update.is_on_ground,
+    );
+}
+
+/// Decode a DF17/DF18 frame and store it like the matching SBS transmission type
+///
+/// Positions of frames derived from multilateration are kept apart from ADS-B positions.
+pub fn decode_message_adsb(
+    data_share: &Arc<Mutex<SharedDataDb>>,
+    source: &str,
+    decoder: &mut AdsbDecoder,
+    frame: &[u8],
+    mlat: bool,
+    received: Option<DateTime<Utc>>,
+) -> Result<(), AdsbError> {
+    let adsb_frame = decoder.decode(frame)?;
+    let hex_ident = adsb_frame.hex_ident;
+
+    // MLAT position with altitude, stored after the regular update created the aircraft
+    let mut mlat_position = None;
+
+    let update = match adsb_frame.message {
+        AdsbMessage::Identification { call_sign, .. } => PlaneUpdate {
+            hex_ident,
+            transmission_type: 1,
+            call_sign: Some(call_sign),
+            ..Default::default()
+        },
+        AdsbMessage::SurfacePosition { ground_speed, track, mut position } => {
+            if mlat {
+                mlat_position = position.take().map(|pos| (pos, None));
+            }
+            PlaneUpdate {
+                hex_ident,
+                transmission_type: 2,
+                ground_speed,
+                track,
+                latitude: position.map(|pos| pos.0 as f32),
+                longitude: position.map(|pos| pos.1 as f32),
+                is_on_ground: Some(true),
+                ..Default::default()
+            }
+        }
+        AdsbMessage::AirbornePosition { altitude, mut position, alert, emergency, spi } => {
+            if mlat {
+                mlat_position = position.take().map(|pos| (pos, altitude));
+            }
+            PlaneUpdate {
+                hex_ident,
+                transmission_type: 3,
+                altitude,
+                latitude: position.map(|pos| pos.0 as f32),
+                longitude: position.map(|pos| pos.1 as f32),
+                alert: Some(alert),
+                emergency: Some(emergency),
+                spi: Some(spi),
+                is_on_ground: Some(false),
+                ..Default::default()
+            }
+        }
+        AdsbMessage::Velocity { ground_speed, track, vertical_rate } => PlaneUpdate {
+            hex_ident,
+            transmission_type: 4,
+            ground_speed,
+            track,
+            vertical_rate,
+            ..Default::default()
+        },
+        // Nothing in SharedDataDb to store it in (yet), same as an all call reply
+        AdsbMessage::OperationalStatus { .. } => PlaneUpdate {
+            hex_ident,
+            transmission_type: 8,
+            ..Default::default()
+        },
+    };
+    let hex_ident = update.hex_ident.clone();
+    store_plane_update(data_share, source, PlaneUpdate { time: received, ..update });
+    if let Some(((lat, lon), altitude)) = mlat_position {
+        let mut data_tmp = data_share.lock().unwrap();
+        let time = received.unwrap_or_else(Utc::now);
+        data_tmp.update_mlat_position(source, hex_ident, time, lat as f32, lon as f32, altitude);
+    }
+    Ok(())
+}
+
+/// ADS-B decoders of one source, frames derived from multilateration are synthetic and
+/// must not be paired with real ones for CPR decoding
+pub struct ModeSDecoders {
+    adsb: AdsbDecoder,
+    mlat: AdsbDecoder,
+    received: Option<DateTime<Utc>>, // See set_received
+}
+
+impl ModeSDecoders {
+    pub fn new(reference: Option<(f64, f64)>) -> ModeSDecoders {
+        ModeSDecoders {
+            adsb: AdsbDecoder::new(reference),
+            mlat: AdsbDecoder::new(reference),
+            received: None,
+        }
+    }
+
+    /// Time of reception of the following frames, e.g. the recording time of a replay, now if `None`
+    pub fn set_received(&mut self, received: Option<DateTime<Utc>>) {
+        self.received = received;
+    }
+}
+
+/// Decode a raw Mode S frame, returns the ICAO address if the frame carries it in plain
+fn decode_message_mode_s(
+    data_share: &Arc<Mutex<SharedDataDb>>,
+    source: &str,
+    decoders: &mut ModeSDecoders,
+    data: &[u8],
+    mlat: bool,
+) -> Option<String> {
+    // Only DF11, DF17 and DF18 carry the plain address, all others XOR it with the parity
+    if data.len() < 7 {
+        return None;
+    }
+    let df = data[0] >> 3;
+    if df != 11 && df != 17 && df != 18 {
+        return None;
+    }
+    let received = decoders.received;
+    let all_call = |hex_ident: String| PlaneUpdate {
+        hex_ident,
+        transmission_type: 8,
+        time: received,
+        ..Default::default()
+    };
+
+    // Same as SBS MSG,8, keeps the aircraft alive without adding data. Noise and broken
+    // frames would create phantom aircraft, so the parity has to match
+    if df == 11 {
+        let hex_ident = all_call_address(data)?;
+        store_plane_update(data_share, source, all_call(hex_ident.clone()));
+        return Some(hex_ident);
+    }
+    let hex_ident = format!("{:02X}{:02X}{:02X}", data[1], data[2], data[3]);
+
+    let decoder = if mlat { &mut decoders.mlat } else { &mut decoders.adsb };
+    match decode_message_adsb(data_share, source, decoder, data, mlat, received) {
+        Ok(()) => {}
+        // Broken frame, the address can't be trusted
+        Err(AdsbError::CrcMismatch(_)) | Err(AdsbError::TooShort(_)) => return None,
+        // Valid frame without anything to store
+        Err(_) => store_plane_update(data_share, source, all_call(hex_ident.clone())),
+    }
+    Some(hex_ident)
+}
+
+/// Decode a Beast frame and store its reception data, the aircraft is identified by its ICAO address
+pub fn decode_message_beast(
+    data_share: &Arc<Mutex<SharedDataDb>>,
+    source: &str,
+    decoders: &mut ModeSDecoders,
+    frame: &BeastFrame,
+) {
+    if frame.frame_type != BeastFrameType::ModeSShort && frame.frame_type != BeastFrameType::ModeSLong {
+        return;
+    }
+
+    let hex_ident = decode_message_mode_s(data_share, source, decoders, &frame.data, frame.is_mlat());
+
+    // Timestamp and signal level of MLAT results are synthetic
+    if let Some(hex_ident) = hex_ident.filter(|_| !frame.is_mlat()) {
+        let mut data_tmp = data_share.lock().unwrap();
+        let time = decoders.received.unwrap_or_else(Utc::now);
+        data_tmp.update_reception(source, hex_ident, time, frame.timestamp, frame.rssi());
+    }
+}
+
+/// Decode one AVR line, e.g. "*8D4840D6202CC371C32CE0576098;", returns false if it has been dropped
+pub fn decode_message_avr(
+    data_share: &Arc<Mutex<SharedDataDb>>,
+    source: &str,
+    decoders: &mut ModeSDecoders,
+    message: &str,
+) -> bool {
+    match parse_avr(message) {
+        Some(frame) => {
+            let _ = decode_message_mode_s(data_share, source, decoders, &frame.data, frame.is_mlat());
+            true
+        }
+        None => false,
+    }
+}
+
+/// Store one aircraft of an aircraft.json, split into the SBS transmission types
+///
+/// Data older than `max_age` seconds has been stored with an earlier poll already.
+pub fn decode_aircraft_json(
+    data_share: &Arc<Mutex<SharedDataDb>>,
+    source: &str,
+    aircraft: &JsonAircraft,
+    max_age: f32,
+    received: Option<DateTime<Utc>>,
+) {
+    for update in aircraft_json_updates(aircraft, max_age) {
+        store_plane_update(data_share, source, PlaneUpdate { time: received, ..update });
+    }
+}
+
+/// Updates of one aircraft of an aircraft.json, see `decode_aircraft_json`
+fn aircraft_json_updates(aircraft: &JsonAircraft, max_age: f32) -> Vec<PlaneUpdate> {
+    let mut updates = vec![];
+    if aircraft.seen.unwrap_or(0.0) > max_age {
+        return updates;
+    }
+    let hex_ident = aircraft.hex_ident();
+
+    // Identification
+    if let Some(flight) = &aircraft.flight {
+        updates.push(PlaneUpdate {
+            hex_ident: hex_ident.clone(),
+            transmission_type: 1,
+            call_sign: Some(flight.trim().to_string()),
+            ..Default::default()
+        });
+    }
+
+    // Position and altitude, aircraft without seen_pos report no position or it is current
+    let position_fresh = aircraft.seen_pos.is_none_or(|seen_pos| seen_pos <= max_age);
+    if aircraft.altitude().is_some() || aircraft.is_on_ground() || position_fresh {
+        updates.push(PlaneUpdate {
+            hex_ident: hex_ident.clone(),
+            transmission_type: if aircraft.is_on_ground() { 2 } else { 3 },
+            altitude: aircraft.altitude(),
+            latitude: aircraft.lat.filter(|_| position_fresh).map(|lat| lat as f32),
+            longitude: aircraft.lon.filter(|_| position_fresh).map(|lon| lon as f32),
+            is_on_ground: Some(aircraft.is_on_ground()),
+            ..Default::default()
+        });
+    }
+
+    // Velocity
+    if aircraft.gs.is_some() || aircraft.track.is_some() || aircraft.baro_rate.is_some() {
+        updates.push(PlaneUpdate {
+            hex_ident: hex_ident.clone(),
+            transmission_type: 4,
+            ground_speed: aircraft.gs,
+            track: aircraft.track,
+            vertical_rate: aircraft.baro_rate,
+            ..Default::default()
+        });
+    }
+
+    // Squawk and emergency flags, the flags are stored without squawk as well
+    let squawk = aircraft.squawk.as_ref().and_then(|squawk| squawk.parse::<i32>().ok());
+    let alert = aircraft.alert.map(|alert| alert == 1);
+    let emergency = aircraft.is_emergency();
+    let spi = aircraft.spi.map(|spi| spi == 1);
+    if squawk.is_some() || alert.is_some() || emergency.is_some() || spi.is_some() {
+        updates.push(PlaneUpdate {
+            hex_ident,
+            transmission_type: 6,
+            squawk,
+            alert,
+            emergency,
+            spi,
+            ..Default::default()
+        });
+    }
+    updates
+}
+
+/// Format of pushed payloads, e.g. MQTT messages
+#[derive(Debug, Deserialize, Clone, Copy, PartialEq, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum PayloadFormat {
+    #[default]
+    Sbs,      // One SBS line
+    SbsBatch, // Several SBS lines, separated by newlines
+    Json,     // One aircraft object, an array of them or a complete aircraft.json
+    BeastHex, // Beast binary data, hex-encoded
+    Avr,      // One or more AVR lines
+    ModeS,    // One binary Mode S frame, 7 or 14 bytes
+}
+
+impl PayloadFormat {
+    /// Guess the format of a text payload, e.g. of a replayed log line
+    pub fn sniff(payload: &str) -> PayloadFormat {
+        let payload = payload.trim();
+        if payload.starts_with('{') || payload.starts_with('[') {
+            PayloadFormat::Json
+        } else if payload.starts_with('*') || payload.starts_with('@') {
+            PayloadFormat::Avr
+        } else if !payload.is_empty() && payload.chars().all(|c| c.is_ascii_hexdigit() || c.is_whitespace()) {
+            PayloadFormat::BeastHex
         } else {
-            // Dropped messages
-            // TODO: Add to statistics for RCA if any
-            dbg!("Warning dropped: {message}");
+            PayloadFormat::SbsBatch
         }
     }
 }
+
+// Pushed JSON may be a whole aircraft.json, a list or a single aircraft
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum JsonPayload {
+    Document(AircraftJson),
+    List(Vec<JsonAircraft>),
+    Single(Box<JsonAircraft>),
+}
+
+/// Decodes payloads of one source, keeps the state needed for binary formats
+pub struct PayloadDecoder {
+    source: String, // Label of the source, e.g. for heartbeats
+    format: PayloadFormat,
+    beast_decoder: BeastDecoder,
+    mode_s_decoders: ModeSDecoders,
+    received: Option<DateTime<Utc>>, // See set_received
+}
+
+impl PayloadDecoder {
+    pub fn new(source: &str, format: PayloadFormat, reference: Option<(f64, f64)>) -> PayloadDecoder {
+        PayloadDecoder {
+            source: source.to_string(),
+            format,
+            beast_decoder: BeastDecoder::new(),
+            mode_s_decoders: ModeSDecoders::new(reference),
+            received: None,
+        }
+    }
+
+    /// Time of reception of the following payloads, e.g. the recording time of a replay, now if `None`
+    pub fn set_received(&mut self, received: Option<DateTime<Utc>>) {
+        self.received = received;
+        self.mode_s_decoders.set_received(received);
+    }
+
+    /// Decode and store a payload, everything that doesn't parse is counted as dropped
+    ///
+    /// Returns the number of dropped messages.
+    pub fn decode(&mut self, data_share: &Arc<Mutex<SharedDataDb>>, payload: &[u8]) -> usize {
+        if self.format == PayloadFormat::ModeS {
+            if payload.len() != 7 && payload.len() != 14 {
+                data_share.lock().unwrap().count_dropped("mode_s: invalid length");
+                return 1;
+            }
+            let _ = decode_message_mode_s(data_share, &self.source, &mut self.mode_s_decoders, payload, false);
+            return 0;
+        }
+
+        let Ok(message) = str::from_utf8(payload) else {
+            data_share.lock().unwrap().count_dropped("payload: not UTF-8");
+            return 1;
+        };
+
+        let mut dropped = Vec::new();
+        let mut sbs_dropped = 0;
+        match self.format {
+            // SBS failures are counted by the SBS decoder itself
+            PayloadFormat::Sbs => {
+                let message = message.trim_end().to_string();
+                if decode_message_sbs(data_share, &self.source, message, self.received).is_err() {
+                    sbs_dropped += 1;
+                }
+            }
+            PayloadFormat::SbsBatch => {
+                for line in message.lines().filter(|line| !line.trim().is_empty()) {
+                    let line = line.trim_end().to_string();
+                    if decode_message_sbs(data_share, &self.source, line, self.received).is_err() {
+                        sbs_dropped += 1;
+                    }
+                }
+            }
+            PayloadFormat::Json => match serde_json::from_str::<JsonPayload>(message) {
+                Ok(JsonPayload::Document(document)) => {
+                    for aircraft in document.aircraft.iter() {
+                        decode_aircraft_json(data_share, &self.source, aircraft, f32::MAX, self.received);
+                    }
+                }
+                Ok(JsonPayload::List(list)) => {
+                    for aircraft in list.iter() {
+                        decode_aircraft_json(data_share, &self.source, aircraft, f32::MAX, self.received);
+                    }
+                }
+                Ok(JsonPayload::Single(aircraft)) => {
+                    decode_aircraft_json(data_share, &self.source, &aircraft, f32::MAX, self.received);
+                }
+                Err(_) => dropped.push("json: invalid payload"),
+            },
+            PayloadFormat::BeastHex => {
+                let hex: String = message.split_whitespace().collect();
+                match hex_to_bytes(&hex) {
+                    Some(bytes) => {
+                        self.beast_decoder.push(&bytes);
+                        while let Some(frame) = self.beast_decoder.next_frame() {
+                            decode_message_beast(data_share, &self.source, &mut self.mode_s_decoders, &frame);
+                        }
+                    }
+                    None => dropped.push("beast: invalid hex"),
+                }
+            }
+            PayloadFormat::Avr => {
+                for line in message.lines().filter(|line| !line.trim().is_empty()) {
+                    if !decode_message_avr(data_share, &self.source, &mut self.mode_s_decoders, line) {
+                        dropped.push("avr: invalid frame");
+                    }
+                }
+            }
+            PayloadFormat::ModeS => {}
+        }
+
+        if !dropped.is_empty() {
+            let mut data_tmp = data_share.lock().unwrap();
+            for reason in dropped.iter() {
+                data_tmp.count_dropped(reason);
+            }
+        }
+        dropped.len() + sbs_dropped
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Entries of a readsb aircraft.json
+    const AIRBORNE: &str = r#"{"hex":"3c6586","type":"adsb_icao","flight":"DLH4RK  ","r":"D-AIUF","t":"A320","alt_baro":36000,"alt_geom":36925,"gs":455.4,"ias":252,"tas":456,"mach":0.784,"track":92.31,"track_rate":0.00,"roll":0.18,"mag_heading":88.77,"true_heading":92.11,"baro_rate":-64,"geom_rate":0,"squawk":"1000","emergency":"none","category":"A3","nav_qnh":1013.6,"nav_altitude_mcp":36000,"lat":53.602875,"lon":10.129395,"nic":8,"rc":186,"seen_pos":0.4,"version":2,"nic_baro":1,"nac_p":9,"nac_v":1,"sil":3,"sil_type":"perhour","gva":2,"sda":2,"alert":0,"spi":0,"mlat":[],"tisb":[],"messages":4217,"seen":0.1,"rssi":-19.8}"#;
+    const GROUND: &str = r#"{"hex":"~2f0a1b","type":"tisb_other","alt_baro":"ground","gs":12.3,"track":180.0,"lat":53.63,"lon":9.99,"seen_pos":25.3,"mlat":[],"tisb":[],"messages":10,"seen":3.2,"rssi":-30.1}"#;
+
+    #[test]
+    fn test_aircraft_json_updates() {
+        let aircraft: JsonAircraft = serde_json::from_str(AIRBORNE).unwrap();
+        let updates = aircraft_json_updates(&aircraft, 2.0);
+        let types: Vec<usize> = updates.iter().map(|update| update.transmission_type).collect();
+        assert_eq!(types, [1, 3, 4, 6]);
+        assert!(updates.iter().all(|update| update.hex_ident == "3C6586"));
+        assert_eq!(updates[0].call_sign.as_deref(), Some("DLH4RK"));
+        assert_eq!(updates[1].altitude, Some(36000.0));
+        assert_eq!(updates[1].latitude, Some(53.602875));
+        assert_eq!(updates[1].is_on_ground, Some(false));
+        assert_eq!((updates[2].ground_speed, updates[2].track, updates[2].vertical_rate), (Some(455.4), Some(92.31), Some(-64.0)));
+        assert_eq!((updates[3].squawk, updates[3].alert, updates[3].emergency, updates[3].spi), (Some(1000), Some(false), Some(false), Some(false)));
+
+        // Stored with an earlier poll already
+        assert!(aircraft_json_updates(&aircraft, 0.05).is_empty());
+
+        // Outdated position isn't stored again
+        let aircraft: JsonAircraft = serde_json::from_str(GROUND).unwrap();
+        let updates = aircraft_json_updates(&aircraft, 10.0);
+        assert_eq!(updates[0].hex_ident, "2F0A1B");
+        assert_eq!((updates[0].transmission_type, updates[0].latitude), (2, None));
+        assert_eq!(updates[0].is_on_ground, Some(true));
+    }
+}
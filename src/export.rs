@@ -0,0 +1,328 @@
+// Export of aircraft tracks to KML, GeoJSON, GPX and CSV, e.g. for Google Earth and QGIS.
+//
+// Triggered in the egui aircraft list or on the command line:
+// luftraum export <kml|geojson|gpx|csv> [--input raw_messages.log] [--output tracks.kml] [--hex 3C6586]...
+// The command line mode loads a recorded log, every aircraft in it is exported.
+
+use chrono::{DateTime, Utc};
+use serde_json::{Value, json};
+use std::fmt::Write;
+use std::sync::{Arc, Mutex};
+
+use crate::data_share::*;
+use crate::network::Receiver;
+use crate::plausibility::Plausibility;
+use crate::replay::load_log;
+
+const FEET_TO_METERS: f32 = 0.3048;
+
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum ExportFormat {
+    #[default]
+    Kml,
+    GeoJson,
+    Gpx,
+    Csv,
+}
+
+impl ExportFormat {
+    pub const ALL: [ExportFormat; 4] = [ExportFormat::Kml, ExportFormat::GeoJson, ExportFormat::Gpx, ExportFormat::Csv];
+
+    pub fn parse(name: &str) -> Option<ExportFormat> {
+        match name.to_ascii_lowercase().as_str() {
+            "kml" => Some(ExportFormat::Kml),
+            "geojson" | "json" => Some(ExportFormat::GeoJson),
+            "gpx" => Some(ExportFormat::Gpx),
+            "csv" => Some(ExportFormat::Csv),
+            _ => None,
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            ExportFormat::Kml => "KML",
+            ExportFormat::GeoJson => "GeoJSON",
+            ExportFormat::Gpx => "GPX",
+            ExportFormat::Csv => "CSV",
+        }
+    }
+
+    pub fn extension(&self) -> &'static str {
+        match self {
+            ExportFormat::Kml => "kml",
+            ExportFormat::GeoJson => "geojson",
+            ExportFormat::Gpx => "gpx",
+            ExportFormat::Csv => "csv",
+        }
+    }
+}
+
+/// History of one aircraft
+#[derive(Debug, Clone)]
+pub struct ExportTrack {
+    pub hex_ident: String,
+    pub call_sign: Option<String>,
+    pub points: Vec<TrackPoint>,
+}
+
+impl ExportTrack {
+    fn name(&self) -> String {
+        match &self.call_sign {
+            Some(call_sign) => format!("{} ({})", call_sign.trim(), self.hex_ident),
+            None => self.hex_ident.clone(),
+        }
+    }
+}
+
+/// Tracks of the given aircraft, all aircraft if `hex_idents` is empty, aircraft without positions are skipped
+pub fn collect_tracks(data_share: &SharedDataDb, hex_idents: &[String]) -> Vec<ExportTrack> {
+    let mut plane_ids: Vec<String> = if hex_idents.is_empty() {
+        data_share.get_planes_id().iter().map(|plane_id| plane_id.to_string()).collect()
+    } else {
+        hex_idents.iter().map(|hex| hex.to_ascii_uppercase()).collect()
+    };
+    plane_ids.sort();
+    plane_ids
+        .into_iter()
+        .map(|plane_id| ExportTrack {
+            call_sign: data_share.get_call_sign(plane_id.clone()),
+            points: data_share.get_track_points(plane_id.clone()),
+            hex_ident: plane_id,
+        })
+        .filter(|track| !track.points.is_empty())
+        .collect()
+}
+
+pub fn export_tracks(tracks: &[ExportTrack], format: ExportFormat) -> String {
+    match format {
+        ExportFormat::Kml => to_kml(tracks),
+        ExportFormat::GeoJson => to_geojson(tracks),
+        ExportFormat::Gpx => to_gpx(tracks),
+        ExportFormat::Csv => to_csv(tracks),
+    }
+}
+
+/// Write tracks to `exports/<name>-<time>.<extension>`, returns the path
+pub fn export_to_file(tracks: &[ExportTrack], format: ExportFormat, name: &str) -> std::io::Result<String> {
+    std::fs::create_dir_all("exports")?;
+    let path = format!(
+        "exports/{}-{}.{}",
+        name,
+        Utc::now().format("%Y%m%d-%H%M%S"),
+        format.extension()
+    );
+    std::fs::write(&path, export_tracks(tracks, format))?;
+    Ok(path)
+}
+
+fn xml_escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+fn iso_time(time: DateTime<Utc>) -> String {
+    time.format("%Y-%m-%dT%H:%M:%SZ").to_string()
+}
+
+fn altitude_meters(point: &TrackPoint) -> f32 {
+    point.altitude.map_or(0.0, |altitude| altitude * FEET_TO_METERS)
+}
+
+/// Placemark per aircraft, extruded to the ground at absolute altitude, points without altitude are skipped
+fn to_kml(tracks: &[ExportTrack]) -> String {
+    let mut kml = String::from(
+        "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n<kml xmlns=\"http://www.opengis.net/kml/2.2\">\n<Document>\n<name>Luftraum</name>\n",
+    );
+    for track in tracks {
+        let _ = write!(
+            kml,
+            "<Placemark>\n<name>{}</name>\n<LineString>\n<extrude>1</extrude>\n<tessellate>0</tessellate>\n<altitudeMode>absolute</altitudeMode>\n<coordinates>\n",
+            xml_escape(&track.name())
+        );
+        // An altitude of 0 would draw a spike down to sea level
+        for point in track.points.iter().filter(|point| point.altitude.is_some()) {
+            let _ = writeln!(kml, "{},{},{:.0}", point.longitude, point.latitude, altitude_meters(point));
+        }
+        kml.push_str("</coordinates>\n</LineString>\n</Placemark>\n");
+    }
+    kml.push_str("</Document>\n</kml>\n");
+    kml
+}
+
+/// LineString feature per aircraft, per-point values as arrays in "coordinateProperties"
+fn to_geojson(tracks: &[ExportTrack]) -> String {
+    let features: Vec<Value> = tracks
+        .iter()
+        .map(|track| {
+            let coordinates: Vec<Value> = track
+                .points
+                .iter()
+                .map(|point| json!([point.longitude, point.latitude, altitude_meters(point)]))
+                .collect();
+            let times: Vec<Option<String>> = track.points.iter().map(|point| point.time.map(iso_time)).collect();
+            json!({
+                "type": "Feature",
+                "geometry": { "type": "LineString", "coordinates": coordinates },
+                "properties": {
+                    "hex_ident": track.hex_ident,
+                    "call_sign": track.call_sign.as_ref().map(|call_sign| call_sign.trim()),
+                    "coordinateProperties": {
+                        "times": times,
+                        "altitude": track.points.iter().map(|point| point.altitude).collect::<Vec<_>>(),
+                        "ground_speed": track.points.iter().map(|point| point.ground_speed).collect::<Vec<_>>(),
+                        "track": track.points.iter().map(|point| point.track).collect::<Vec<_>>(),
+                        "vertical_rate": track.points.iter().map(|point| point.vertical_rate).collect::<Vec<_>>(),
+                        "squawk": track.points.iter().map(|point| point.squawk).collect::<Vec<_>>(),
+                        "source": track.points.iter().map(|point| point.source.clone()).collect::<Vec<_>>(),
+                    },
+                },
+            })
+        })
+        .collect();
+    let collection = json!({ "type": "FeatureCollection", "features": features });
+    serde_json::to_string_pretty(&collection).unwrap_or_default()
+}
+
+/// Track per aircraft, elevation in meters
+fn to_gpx(tracks: &[ExportTrack]) -> String {
+    let mut gpx = String::from(
+        "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n<gpx version=\"1.1\" creator=\"Luftraum\" xmlns=\"http://www.topografix.com/GPX/1/1\">\n",
+    );
+    for track in tracks {
+        let _ = write!(gpx, "<trk>\n<name>{}</name>\n<trkseg>\n", xml_escape(&track.name()));
+        for point in track.points.iter() {
+            let _ = write!(gpx, "<trkpt lat=\"{}\" lon=\"{}\">", point.latitude, point.longitude);
+            if point.altitude.is_some() {
+                let _ = write!(gpx, "<ele>{:.0}</ele>", altitude_meters(point));
+            }
+            if let Some(time) = point.time {
+                let _ = write!(gpx, "<time>{}</time>", iso_time(time));
+            }
+            gpx.push_str("</trkpt>\n");
+        }
+        gpx.push_str("</trkseg>\n</trk>\n");
+    }
+    gpx.push_str("</gpx>\n");
+    gpx
+}
+
+/// One row per position, altitude in feet as received
+fn to_csv(tracks: &[ExportTrack]) -> String {
+    let mut csv = String::from("hex_ident,call_sign,time,latitude,longitude,altitude,ground_speed,track,vertical_rate,squawk,source\n");
+    let optional = |value: Option<f32>| value.map(|value| value.to_string()).unwrap_or_default();
+    for track in tracks {
+        for point in track.points.iter() {
+            let _ = writeln!(
+                csv,
+                "{},{},{},{},{},{},{},{},{},{},{}",
+                track.hex_ident,
+                track.call_sign.as_deref().unwrap_or("").trim().replace(',', " "),
+                point.time.map(iso_time).unwrap_or_default(),
+                point.latitude,
+                point.longitude,
+                optional(point.altitude),
+                optional(point.ground_speed),
+                optional(point.track),
+                optional(point.vertical_rate),
+                point.squawk.map(|squawk| format!("{:04}", squawk)).unwrap_or_default(),
+                point.source.replace(',', " "),
+            );
+        }
+    }
+    csv
+}
+
+/// Command line mode, `args` are the arguments following "export"
+pub async fn run_export_command(
+    args: &[String], receiver: Option<Receiver>, history: History, plausibility: Plausibility,
+) -> Result<(), String> {
+    let usage = "Usage: luftraum export <kml|geojson|gpx|csv> [--input FILE] [--output FILE] [--hex HEX]...";
+    let mut args = args.iter();
+    let format = args
+        .next()
+        .and_then(|format| ExportFormat::parse(format))
+        .ok_or(usage.to_string())?;
+    let mut input = "raw_messages.log".to_string();
+    let mut output: Option<String> = None;
+    let mut hex_idents: Vec<String> = vec![];
+    while let Some(arg) = args.next() {
+        let value = args.next().ok_or(usage.to_string())?.clone();
+        match arg.as_str() {
+            "--input" => input = value,
+            "--output" => output = Some(value),
+            "--hex" => hex_idents.push(value),
+            _ => return Err(usage.to_string()),
+        }
+    }
+
+    let mut data_tmp = SharedDataDb::new();
+    data_tmp.set_history(history);
+    data_tmp.set_plausibility(plausibility, receiver.as_ref().map(|r| (r.latitude as f32, r.longitude as f32)));
+    let data_share = Arc::new(Mutex::new(data_tmp));
+    load_log(&data_share, &input, receiver)
+        .await
+        .map_err(|e| format!("Loading {}, error: {}", input, e))?;
+    let tracks = collect_tracks(&data_share.lock().unwrap(), &hex_idents);
+
+    let exported = export_tracks(&tracks, format);
+    match output {
+        Some(output) => {
+            std::fs::write(&output, exported).map_err(|e| format!("Writing {}, error: {}", output, e))?;
+            println!("Exported {} aircraft to {}", tracks.len(), output);
+        }
+        None => print!("{}", exported),
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn track() -> ExportTrack {
+        let point = TrackPoint {
+            time: DateTime::from_timestamp(1700000000, 0),
+            latitude: 53.5,
+            longitude: 10.0,
+            altitude: Some(10000.0),
+            ground_speed: Some(420.0),
+            track: Some(90.0),
+            vertical_rate: None,
+            squawk: Some(1000),
+            source: "Antenne SBS".to_string(),
+        };
+        ExportTrack {
+            hex_ident: "3C6586".to_string(),
+            call_sign: Some("DLH123  ".to_string()),
+            points: vec![point.clone(), TrackPoint { latitude: 53.6, altitude: None, ..point }],
+        }
+    }
+
+    #[test]
+    fn test_export_tracks() {
+        let tracks = [track()];
+
+        let kml = export_tracks(&tracks, ExportFormat::Kml);
+        assert!(kml.contains("<name>DLH123 (3C6586)</name>"));
+        assert!(kml.contains("<extrude>1</extrude>"));
+        assert!(kml.contains("10,53.5,3048\n</coordinates>"));
+
+        let geojson: Value = serde_json::from_str(&export_tracks(&tracks, ExportFormat::GeoJson)).unwrap();
+        let feature = &geojson["features"][0];
+        assert_eq!(feature["geometry"]["coordinates"][0], json!([10.0, 53.5, 3048.0]));
+        assert_eq!(feature["properties"]["coordinateProperties"]["times"][0], "2023-11-14T22:13:20Z");
+        assert_eq!(feature["properties"]["coordinateProperties"]["altitude"][1], Value::Null);
+
+        let gpx = export_tracks(&tracks, ExportFormat::Gpx);
+        assert!(gpx.contains("<trkpt lat=\"53.5\" lon=\"10\"><ele>3048</ele><time>2023-11-14T22:13:20Z</time></trkpt>"));
+        assert!(gpx.contains("<trkpt lat=\"53.6\" lon=\"10\"><time>2023-11-14T22:13:20Z</time></trkpt>"));
+
+        let csv = export_tracks(&tracks, ExportFormat::Csv);
+        assert_eq!(
+            csv.lines().nth(1),
+            Some("3C6586,DLH123,2023-11-14T22:13:20Z,53.5,10,10000,420,90,,1000,Antenne SBS")
+        );
+    }
+}
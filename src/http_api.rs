@@ -0,0 +1,194 @@
+// HTTP API in the readsb schema, tar1090 and dashboards can use luftraum instead of readsb.
+//
+// Serves /data/aircraft.json and /data/receiver.json, everything else is 404.
+
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime};
+use serde::{Deserialize, Serialize};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+
+use crate::aircraft_json::{AircraftJson, AltBaro, JsonAircraft};
+use crate::data_share::*;
+use crate::network::Receiver;
+
+// Requests are small, anything bigger is not from tar1090
+const MAX_REQUEST_SIZE: usize = 8192;
+// Clients that do not send a complete header in time are dropped
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(5);
+
+#[derive(Debug, Deserialize, Clone)]
+pub struct HttpApi {
+    #[serde(default = "default_http_bind_address")]
+    pub http_bind_address: String,
+    pub http_port: u16,
+}
+
+fn default_http_bind_address() -> String {
+    "127.0.0.1".to_string()
+}
+
+#[derive(Debug, Serialize)]
+struct ReceiverJson {
+    version: String,
+    refresh: u32, // Milliseconds between updates of aircraft.json
+    history: u32, // No history files
+    #[serde(skip_serializing_if = "Option::is_none")]
+    lat: Option<f64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    lon: Option<f64>,
+}
+
+/// Serve aircraft.json and receiver.json until the listener fails
+pub async fn serve_http_api(
+    data_share: &Arc<Mutex<SharedDataDb>>, http_api: HttpApi, receiver: Option<Receiver>,
+) -> Result<(), Box<dyn std::error::Error>> {
+
+    let addr = format!("{}:{}", http_api.http_bind_address, http_api.http_port);
+    let listener = TcpListener::bind(&addr).await?;
+    println!("Serving HTTP API on http://{}/data/aircraft.json", addr);
+
+    loop {
+        let (stream, _) = match listener.accept().await {
+            Ok(accepted) => accepted,
+            Err(e) => {
+                eprintln!("Error accepting HTTP client on {}. Error: {:?}", addr, e);
+                continue;
+            }
+        };
+        let data_share = data_share.clone();
+        let receiver = receiver.clone();
+        tokio::spawn(async move {
+            if let Err(e) = handle_request(&data_share, stream, receiver).await {
+                eprintln!("HTTP API, error: {}", e);
+            }
+        });
+    }
+}
+
+/// One request per connection, enough for polling clients
+async fn handle_request(
+    data_share: &Arc<Mutex<SharedDataDb>>, mut stream: TcpStream, receiver: Option<Receiver>,
+) -> Result<(), Box<dyn std::error::Error>> {
+
+    // Read the header, the body of GET requests is empty
+    let mut request = Vec::new();
+    let mut buffer = [0u8; 1024];
+    let read_header = async {
+        while !request.windows(4).any(|window| window == b"\r\n\r\n") {
+            let len = stream.read(&mut buffer).await?;
+            if len == 0 || request.len() + len > MAX_REQUEST_SIZE {
+                return Ok(false);
+            }
+            request.extend_from_slice(&buffer[..len]);
+        }
+        Ok::<bool, std::io::Error>(true)
+    };
+    match tokio::time::timeout(REQUEST_TIMEOUT, read_header).await {
+        Ok(Ok(true)) => {}
+        Ok(Ok(false)) | Err(_) => return Ok(()),
+        Ok(Err(e)) => return Err(e.into()),
+    }
+
+    // Request line, e.g. "GET /data/aircraft.json?_=1700000000 HTTP/1.1"
+    let request = String::from_utf8_lossy(&request);
+    let mut request_line = request.lines().next().unwrap_or("").split_whitespace();
+    let method = request_line.next().unwrap_or("");
+    let path = request_line.next().unwrap_or("").split('?').next().unwrap_or("");
+
+    let (status, body) = if method != "GET" && method != "HEAD" {
+        ("405 Method Not Allowed", String::new())
+    } else if path.ends_with("/data/aircraft.json") {
+        let aircraft_json = build_aircraft_json(&data_share.lock().unwrap());
+        ("200 OK", serde_json::to_string(&aircraft_json)?)
+    } else if path.ends_with("/data/receiver.json") {
+        ("200 OK", serde_json::to_string(&build_receiver_json(receiver))?)
+    } else {
+        ("404 Not Found", String::new())
+    };
+
+    let header = format!(
+        "HTTP/1.1 {}\r\nContent-Type: application/json\r\nContent-Length: {}\r\nCache-Control: no-cache\r\nAccess-Control-Allow-Origin: *\r\nConnection: close\r\n\r\n",
+        status,
+        body.len()
+    );
+    stream.write_all(header.as_bytes()).await?;
+    if method != "HEAD" {
+        stream.write_all(body.as_bytes()).await?;
+    }
+    stream.shutdown().await?;
+    Ok(())
+}
+
+fn build_receiver_json(receiver: Option<Receiver>) -> ReceiverJson {
+    ReceiverJson {
+        version: format!("luftraum {}", env!("CARGO_PKG_VERSION")),
+        refresh: 1000,
+        history: 0,
+        lat: receiver.as_ref().map(|r| r.latitude),
+        lon: receiver.as_ref().map(|r| r.longitude),
+    }
+}
+
+/// Current state of all aircraft in the readsb schema, enriched with hexdb.io data
+fn build_aircraft_json(data_share: &SharedDataDb) -> AircraftJson {
+    let now = SystemTime::now()
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .unwrap()
+        .as_secs_f64();
+    let add_data = AIRCRAFT_ADD_DATA.lock().ok();
+
+    let mut messages = 0;
+    let mut aircraft = vec![];
+    for plane_id in data_share.get_planes_id() {
+        let plane_id = plane_id.to_string();
+        let message_count = data_share.get_message_count(plane_id.clone()) as u64;
+        messages += message_count;
+
+        let position = data_share.get_latest_known_pos_any(plane_id.clone());
+        let alt_baro = if data_share.is_on_ground(plane_id.clone()) == Some(true) {
+            Some(AltBaro::Ground("ground".to_string()))
+        } else {
+            data_share
+                .get_latest_known_altitude(plane_id.clone())
+                .map(AltBaro::Feet)
+        };
+        let added_data = add_data.as_ref().and_then(|data| data.get(&plane_id));
+        let text = |value: &str| Some(value.to_string()).filter(|value| !value.is_empty());
+
+        aircraft.push(JsonAircraft {
+            hex: plane_id.to_lowercase(),
+            source_type: Some(match position.map(|pos| pos.3) {
+                Some(PositionSource::Mlat) => "mlat".to_string(),
+                _ => "adsb_icao".to_string(),
+            }),
+            // readsb pads call signs to 8 characters
+            flight: data_share
+                .get_call_sign(plane_id.clone())
+                .map(|call_sign| format!("{:<8}", call_sign)),
+            alt_baro,
+            gs: data_share.get_ground_speed(plane_id.clone()),
+            track: data_share.get_track(plane_id.clone()),
+            lat: position.map(|pos| pos.0 as f64),
+            lon: position.map(|pos| pos.1 as f64),
+            baro_rate: data_share.get_vertical_rate(plane_id.clone()),
+            squawk: data_share
+                .get_squawk(plane_id.clone())
+                .map(|squawk| format!("{:04}", squawk)),
+            messages: Some(message_count),
+            seen: data_share.get_last_seen_secs(plane_id.clone()),
+            rssi: data_share.get_signal_level(plane_id.clone()),
+            r: added_data.and_then(|data| text(&data.registration)),
+            t: added_data.and_then(|data| text(&data.icao_type_code)),
+            desc: added_data.and_then(|data| text(&data.aircraft_type)),
+            own_op: added_data.and_then(|data| text(&data.registered_owners)),
+            ..Default::default()
+        });
+    }
+
+    AircraftJson {
+        now: Some(now),
+        messages: Some(messages),
+        aircraft,
+    }
+}
@@ -1,22 +1,341 @@
-use std::fs::OpenOptions;
-use std::io::{BufWriter, Write};
-use std::time::SystemTime;
-
-/// Logs all received ADB-S data to a static file.
-pub fn log_messages(src: &str, message: &str) -> std::io::Result<()> {
-    let file = OpenOptions::new()
-        .create(true)
-        .append(true)
-        .open("raw_messages.log")?;
-
-    let mut writer = BufWriter::new(file);
-    let timestamp = SystemTime::now()
+// Raw message log, e.g. raw_messages.log for replays.
+//
+// Network tasks hand every message to `log_messages`, a background task writes them in
+// batches, rotates the file daily or by size, gzips rotated files and removes old ones.
+// After a failed batch the file is reopened with a backoff, messages meanwhile are dropped.
+// Rotated files are named "<path>.<YYYYmmdd-HHMMSS>" after the time the file was started,
+// or last changed if it was continued from a previous run.
+
+use chrono::{DateTime, Utc};
+use flate2::Compression;
+use flate2::write::GzEncoder;
+use once_cell::sync::OnceCell;
+use serde::{Deserialize, Serialize};
+use std::borrow::Cow;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::{Duration, SystemTime};
+use tokio::fs::{File, OpenOptions};
+use tokio::io::{AsyncWriteExt, BufWriter};
+use tokio::sync::mpsc;
+use tokio::time::sleep;
+
+use crate::source_health::Backoff;
+
+// Messages waiting for the writer, further messages are dropped
+const RAW_LOG_CAPACITY: usize = 16384;
+// Delays before reopening the file after an error, e.g. a full disk
+const REOPEN_DELAY: Duration = Duration::from_secs(1);
+const REOPEN_MAX_DELAY: Duration = Duration::from_secs(60);
+
+#[derive(Debug, Deserialize, Clone)]
+pub struct RawLog {
+    #[serde(default = "default_raw_log_enabled")]
+    pub enabled: bool,
+    #[serde(default = "default_raw_log_path")]
+    pub path: String,
+    #[serde(default)]
+    pub format: RawLogFormat,
+    #[serde(default)]
+    pub rotation: RawLogRotation,
+    #[serde(default = "default_raw_log_max_size_mb")]
+    pub max_size_mb: u64, // Used by size based rotation
+    #[serde(default)]
+    pub compress: bool, // Gzip rotated files
+    #[serde(default)]
+    pub retention: Option<usize>, // Number of rotated files to keep, all if not set
+}
+
+impl Default for RawLog {
+    fn default() -> Self {
+        RawLog {
+            enabled: default_raw_log_enabled(),
+            path: default_raw_log_path(),
+            format: RawLogFormat::default(),
+            rotation: RawLogRotation::default(),
+            max_size_mb: default_raw_log_max_size_mb(),
+            compress: false,
+            retention: None,
+        }
+    }
+}
+
+fn default_raw_log_enabled() -> bool {
+    true
+}
+
+fn default_raw_log_path() -> String {
+    "raw_messages.log".to_string()
+}
+
+fn default_raw_log_max_size_mb() -> u64 {
+    100
+}
+
+#[derive(Debug, Deserialize, Clone, Copy, Default, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum RawLogFormat {
+    #[default]
+    Csv,  // <unix time>,<source>,<message>, unix time with milliseconds, e.g. 1700000000.123
+    Json, // {"time": <unix time>, "source": "<source>", "message": "<message>"}
+}
+
+#[derive(Debug, Deserialize, Clone, Copy, Default, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum RawLogRotation {
+    #[default]
+    Never,
+    Daily, // At midnight UTC
+    Size,  // When max_size_mb is exceeded
+}
+
+/// One line of the json format, messages keep their newlines
+#[derive(Debug, Serialize, Deserialize, PartialEq)]
+pub struct JsonLogRecord<'a> {
+    pub time: f64, // Seconds with milliseconds, whole seconds in older logs
+    #[serde(borrow)]
+    pub source: Cow<'a, str>,
+    #[serde(borrow)]
+    pub message: Cow<'a, str>,
+}
+
+struct LogRecord {
+    timestamp_ms: u64, // Unix time in milliseconds
+    source: String,
+    message: String,
+}
+
+static RAW_LOG: OnceCell<mpsc::Sender<LogRecord>> = OnceCell::new();
+static DROPPED: AtomicUsize = AtomicUsize::new(0);
+
+/// Logs a received message, does nothing if the raw log is not running
+pub fn log_messages(src: &str, message: &str) {
+    let Some(sender) = RAW_LOG.get() else {
+        return;
+    };
+    let timestamp_ms = SystemTime::now()
         .duration_since(SystemTime::UNIX_EPOCH)
         .unwrap()
-        .as_secs();
+        .as_millis() as u64;
+    let record = LogRecord {
+        timestamp_ms,
+        source: src.to_string(),
+        message: message.to_string(),
+    };
+    if sender.try_send(record).is_err() {
+        DROPPED.fetch_add(1, Ordering::Relaxed);
+    }
+}
+
+/// Start the background writer, has to be called once from within the tokio runtime
+pub fn start_raw_log(raw_log: RawLog) {
+    if !raw_log.enabled {
+        return;
+    }
+    let (sender, receiver) = mpsc::channel(RAW_LOG_CAPACITY);
+    if RAW_LOG.set(sender).is_err() {
+        return;
+    }
+    tokio::spawn(write_raw_log(raw_log, receiver));
+}
+
+/// Line of a record in the configured format, including the newline
+fn format_record(format: RawLogFormat, timestamp_ms: u64, source: &str, message: &str) -> String {
+    match format {
+        RawLogFormat::Csv => format!("{}.{:03},{},{}\n", timestamp_ms / 1000, timestamp_ms % 1000, source, message),
+        RawLogFormat::Json => {
+            let record = JsonLogRecord {
+                time: timestamp_ms as f64 / 1000.0,
+                source: Cow::Borrowed(source),
+                message: Cow::Borrowed(message),
+            };
+            serde_json::to_string(&record).unwrap_or_default() + "\n"
+        }
+    }
+}
 
-    writeln!(writer, "{},{},{}", timestamp, src, message)?;
-    writer.flush()?;
+// The file currently written
+struct LogFile {
+    writer: BufWriter<File>,
+    started: DateTime<Utc>,
+    size: u64,
+}
 
+async fn open_log_file(path: &str) -> std::io::Result<LogFile> {
+    // E.g. logs/raw_messages.log
+    if let Some(directory) = Path::new(path).parent()
+        && !directory.as_os_str().is_empty()
+    {
+        tokio::fs::create_dir_all(directory).await?;
+    }
+    let file = OpenOptions::new().create(true).append(true).open(path).await?;
+    let metadata = file.metadata().await?;
+    // Appending to an existing file, it belongs to the day of its last change
+    let started = metadata.modified().map(DateTime::<Utc>::from).unwrap_or_else(|_| Utc::now());
+    Ok(LogFile {
+        writer: BufWriter::new(file),
+        started,
+        size: metadata.len(),
+    })
+}
+
+async fn write_raw_log(raw_log: RawLog, mut receiver: mpsc::Receiver<LogRecord>) {
+    let mut log_file: Option<LogFile> = None; // Opened on the next batch
+    let mut backoff = Backoff::new(REOPEN_DELAY, REOPEN_MAX_DELAY);
+
+    while let Some(record) = receiver.recv().await {
+        // Write everything waiting, flush once per batch
+        let mut batch = vec![record];
+        while let Ok(record) = receiver.try_recv() {
+            batch.push(record);
+        }
+        match write_batch(&raw_log, log_file.take(), &batch).await {
+            Ok(written) => {
+                log_file = Some(written);
+                backoff.reset();
+            }
+            Err(e) => {
+                // The batch is lost, reported with the messages dropped by a slow writer
+                DROPPED.fetch_add(batch.len(), Ordering::Relaxed);
+                let delay = backoff.next_delay();
+                eprintln!("Raw log, writing {}, error: {}, reopening in {:?}", raw_log.path, e, delay);
+                sleep(delay).await;
+            }
+        }
+
+        let dropped = DROPPED.swap(0, Ordering::Relaxed);
+        if dropped > 0 {
+            eprintln!("Raw log, writer too slow or failing: {} messages dropped", dropped);
+        }
+    }
+}
+
+/// Write and flush a batch, the file is opened if there is none and dropped on errors
+async fn write_batch(raw_log: &RawLog, log_file: Option<LogFile>, batch: &[LogRecord]) -> std::io::Result<LogFile> {
+    let mut log_file = match log_file {
+        Some(log_file) => log_file,
+        None => open_log_file(&raw_log.path).await?,
+    };
+    let max_size = raw_log.max_size_mb * 1024 * 1024;
+
+    for record in batch {
+        let rotate = match raw_log.rotation {
+            RawLogRotation::Never => false,
+            RawLogRotation::Daily => {
+                DateTime::from_timestamp_millis(record.timestamp_ms as i64)
+                    .is_some_and(|time| time.date_naive() != log_file.started.date_naive())
+            }
+            RawLogRotation::Size => log_file.size >= max_size,
+        };
+        if rotate && log_file.size > 0 {
+            log_file.writer.flush().await?;
+            rotate_log_file(raw_log, log_file.started).await?;
+            log_file = open_log_file(&raw_log.path).await?;
+            log_file.started = Utc::now();
+        }
+
+        let line = format_record(raw_log.format, record.timestamp_ms, &record.source, &record.message);
+        log_file.writer.write_all(line.as_bytes()).await?;
+        log_file.size += line.len() as u64;
+    }
+    log_file.writer.flush().await?;
+    Ok(log_file)
+}
+
+/// Rename the current file, compression and removal of old files run in the background
+async fn rotate_log_file(raw_log: &RawLog, started: DateTime<Utc>) -> std::io::Result<()> {
+    let rotated = PathBuf::from(format!("{}.{}", raw_log.path, started.format("%Y%m%d-%H%M%S")));
+    tokio::fs::rename(&raw_log.path, &rotated).await?;
+
+    let raw_log = raw_log.clone();
+    tokio::task::spawn_blocking(move || {
+        if raw_log.compress
+            && let Err(e) = compress_file(&rotated)
+        {
+            eprintln!("Raw log, compressing {}, error: {}", rotated.display(), e);
+        }
+        if let Some(retention) = raw_log.retention
+            && let Err(e) = remove_old_files(Path::new(&raw_log.path), retention)
+        {
+            eprintln!("Raw log, removing old files, error: {}", e);
+        }
+    });
     Ok(())
-}
\ No newline at end of file
+}
+
+/// Gzip a file to "<file>.gz" and remove the original
+fn compress_file(path: &Path) -> std::io::Result<()> {
+    let mut gz_path = path.as_os_str().to_owned();
+    gz_path.push(".gz");
+    let mut input = std::fs::File::open(path)?;
+    let mut encoder = GzEncoder::new(std::fs::File::create(&gz_path)?, Compression::default());
+    std::io::copy(&mut input, &mut encoder)?;
+    encoder.finish()?;
+    std::fs::remove_file(path)
+}
+
+/// Rotated files of a log, oldest first
+fn rotated_files(path: &Path) -> std::io::Result<Vec<PathBuf>> {
+    let directory = match path.parent() {
+        Some(directory) if !directory.as_os_str().is_empty() => directory,
+        _ => Path::new("."),
+    };
+    let prefix = format!("{}.", path.file_name().unwrap_or_default().to_string_lossy());
+    let mut files: Vec<PathBuf> = std::fs::read_dir(directory)?
+        .filter_map(|entry| entry.ok())
+        .map(|entry| entry.path())
+        .filter(|file| {
+            file.file_name()
+                .is_some_and(|name| name.to_string_lossy().starts_with(&prefix))
+        })
+        .collect();
+    // The timestamp in the name sorts chronologically
+    files.sort();
+    Ok(files)
+}
+
+fn remove_old_files(path: &Path, retention: usize) -> std::io::Result<()> {
+    let files = rotated_files(path)?;
+    let remove = files.len().saturating_sub(retention);
+    for file in files.iter().take(remove) {
+        std::fs::remove_file(file)?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_format_record() {
+        assert_eq!(
+            format_record(RawLogFormat::Csv, 1700000000045, "sbs", "MSG,3,1,1,4CA2D6"),
+            "1700000000.045,sbs,MSG,3,1,1,4CA2D6\n"
+        );
+        let line = format_record(RawLogFormat::Json, 1700000000123, "adsb/sbs", "MSG,3\nMSG,4");
+        assert_eq!(line, "{\"time\":1700000000.123,\"source\":\"adsb/sbs\",\"message\":\"MSG,3\\nMSG,4\"}\n");
+        let record: JsonLogRecord = serde_json::from_str(line.trim_end()).unwrap();
+        assert_eq!(record.message, "MSG,3\nMSG,4");
+    }
+
+    #[test]
+    fn test_remove_old_files() {
+        let directory = std::env::temp_dir().join(format!("luftraum-raw-log-{}", std::process::id()));
+        std::fs::create_dir_all(&directory).unwrap();
+        let path = directory.join("raw_messages.log");
+        for name in ["raw_messages.log", "raw_messages.log.20240101-000000.gz", "raw_messages.log.20240102-000000", "raw_messages.log.20240103-000000.gz"] {
+            std::fs::write(directory.join(name), "").unwrap();
+        }
+
+        remove_old_files(&path, 2).unwrap();
+        let names: Vec<String> = rotated_files(&path)
+            .unwrap()
+            .iter()
+            .map(|file| file.file_name().unwrap().to_string_lossy().to_string())
+            .collect();
+        assert_eq!(names, ["raw_messages.log.20240102-000000", "raw_messages.log.20240103-000000.gz"]);
+        assert!(path.exists());
+        std::fs::remove_dir_all(&directory).unwrap();
+    }
+}
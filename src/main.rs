@@ -4,8 +4,14 @@ use std::collections::HashMap;
 use std::sync::{Arc, Mutex};
 use std::{error::Error, fs, process};
 
-use crate::data_share::SharedDataDb;
+use crate::data_share::{expire_planes, Expiry, History, SharedDataDb};
+use crate::export::run_export_command;
 use crate::hex_lookup::*;
+use crate::http_api::*;
+use crate::logging::*;
+use crate::plausibility::Plausibility;
+use crate::replay::*;
+use crate::websocket::*;
 use crate::network::*;
 
 use jemallocator::Jemalloc;
@@ -13,24 +19,36 @@ use jemallocator::Jemalloc;
 #[global_allocator]
 static GLOBAL: Jemalloc = Jemalloc;
 
+mod adsb;
+mod aircraft_json;
+mod avr;
+mod beast;
 mod data_share;
 mod decode;
+mod export;
 mod hex_lookup;
+mod http_api;
 mod logging;
 pub mod math;
+mod motion;
 mod network;
+mod plausibility;
 mod plugin_airspace;
 mod plugin_antenna;
+mod plugin_bridge;
 mod plugin_egui;
 mod plugin_ground_structures;
 mod plugin_plane;
-//mod sbs;
+mod sbs;
 mod setup;
+mod source_health;
 mod squawks;
 mod srtm;
 mod terrain;
 mod terrain_color_spectrum;
+mod websocket;
 mod plugin_sound;
+mod replay;
 
 #[derive(Resource)]
 struct ShareStruct(Arc<Mutex<SharedDataDb>>);
@@ -38,7 +56,23 @@ struct ShareStruct(Arc<Mutex<SharedDataDb>>);
 #[derive(Debug, Deserialize, Clone)]
 struct Configuration {
     sbs_server: Option<Vec<SbsServer>>,
+    beast_server: Option<Vec<BeastServer>>,
+    avr_server: Option<Vec<AvrServer>>,
+    aircraft_json: Option<Vec<AircraftJsonSource>>,
+    udp_listener: Option<Vec<UdpListener>>,
+    receiver: Option<Receiver>,
     mqtt_broker: Option<Vec<MqttBroker>>,
+    mqtt_publisher: Option<Vec<MqttPublisher>>,
+    replay: Option<Replay>,
+    raw_log: Option<RawLog>,
+    history: Option<History>,
+    expiry: Option<Expiry>,
+    plausibility: Option<Plausibility>,
+    sbs_output: Option<SbsOutput>,
+    http_api: Option<HttpApi>,
+    websocket: Option<WebSocketServer>,
+    #[serde(default)]
+    headless: bool, // No window, same as the command line option --headless
     //terrain_tile_size: TerrainTileSize,
     //terrain_srtm_file: Vec<TerrainSrtmFile>,
 }
@@ -52,15 +86,47 @@ async fn main() {
         process::exit(1);
     });
 
+    // Command line export of a recorded log, no window is opened
+    let args: Vec<String> = std::env::args().collect();
+    if args.get(1).map(String::as_str) == Some("export") {
+        if let Err(e) = run_export_command(
+            &args[2..],
+            config.receiver.clone(),
+            config.history.unwrap_or_default(),
+            config.plausibility.unwrap_or_default(),
+        )
+        .await
+        {
+            eprintln!("{}", e);
+            process::exit(1);
+        }
+        return;
+    }
+
     // Create struct to store all aircraft data and share it between the network and bevy tasks.
-    let plane_data_db = SharedDataDb::new();
+    let mut plane_data_db = SharedDataDb::new();
+    plane_data_db.set_history(config.history.unwrap_or_default());
+    plane_data_db.set_plausibility(
+        config.plausibility.unwrap_or_default(),
+        config.receiver.as_ref().map(|r| (r.latitude as f32, r.longitude as f32)),
+    );
     let shared_plane_data_db = Arc::new(Mutex::new(plane_data_db));
     let bevy_plane_data_db = shared_plane_data_db.clone();
 
+    // Age out aircraft, independent of the Bevy window
+    let tokio_plane_data_db_expiry = shared_plane_data_db.clone();
+    let expiry = config.expiry.unwrap_or_default();
+    tokio::spawn(async move {
+        expire_planes(&tokio_plane_data_db_expiry, expiry).await;
+    });
+
     // Create struct to store additional aircraft data
     let aircraft_additional_data: HashMap<&str, Aircraft> = HashMap::new();
     let _shared_aircraft_additional_data = Arc::new(Mutex::new(aircraft_additional_data));
 
+    // Write received messages to raw_messages.log, unless disabled
+    start_raw_log(config.raw_log.clone().unwrap_or_default());
+
     // Receive ADS-B data from dump1090
     for sbs_servers in config.clone().sbs_server.into_iter() {
         for sbs_server in sbs_servers {
@@ -71,16 +137,125 @@ async fn main() {
         }
     }
 
+    // Receive ADS-B data from Beast binary feeds
+    for beast_servers in config.clone().beast_server.into_iter() {
+        for beast_server in beast_servers {
+            let tokio_plane_data_db_beast = shared_plane_data_db.clone();
+            let receiver = config.receiver.clone();
+            tokio::spawn(async move {
+                let _ = connect_dump1090_beast(&tokio_plane_data_db_beast, beast_server, receiver).await;
+            });
+        }
+    }
+
+    // Receive ADS-B data from AVR raw hex feeds
+    for avr_servers in config.clone().avr_server.into_iter() {
+        for avr_server in avr_servers {
+            let tokio_plane_data_db_avr = shared_plane_data_db.clone();
+            let receiver = config.receiver.clone();
+            tokio::spawn(async move {
+                let _ = connect_dump1090_avr(&tokio_plane_data_db_avr, avr_server, receiver).await;
+            });
+        }
+    }
+
+    // Poll aircraft.json of readsb/tar1090 stations
+    for aircraft_json_sources in config.clone().aircraft_json.into_iter() {
+        for aircraft_json_source in aircraft_json_sources {
+            let tokio_plane_data_db_json = shared_plane_data_db.clone();
+            tokio::spawn(async move {
+                let _ = poll_aircraft_json(&tokio_plane_data_db_json, aircraft_json_source).await;
+            });
+        }
+    }
+
+    // Receive ADS-B data pushed via UDP
+    for udp_listeners in config.clone().udp_listener.into_iter() {
+        for udp_listener in udp_listeners {
+            let tokio_plane_data_db_udp = shared_plane_data_db.clone();
+            let receiver = config.receiver.clone();
+            tokio::spawn(async move {
+                if let Err(e) = listen_udp(&tokio_plane_data_db_udp, udp_listener, receiver).await {
+                    eprintln!("UDP listener, error: {}", e);
+                }
+            });
+        }
+    }
+
     // Receive ADS-B data from MQTT subscriptions
-    for mqtt_brokers in config.mqtt_broker.into_iter() {
+    for mqtt_brokers in config.clone().mqtt_broker.into_iter() {
         for mqtt_broker in mqtt_brokers {
             let tokio_plane_data_db_mqtt = shared_plane_data_db.clone();
+            let receiver = config.receiver.clone();
+            tokio::spawn(async move {
+                if let Err(e) = connect_mqtt(&tokio_plane_data_db_mqtt, mqtt_broker, receiver).await {
+                    eprintln!("MQTT broker, error: {}", e);
+                }
+            });
+        }
+    }
+
+    // Replay a recorded raw_messages.log
+    if let Some(replay) = config.replay.clone() {
+        let tokio_plane_data_db_replay = shared_plane_data_db.clone();
+        let receiver = config.receiver.clone();
+        tokio::spawn(async move {
+            if let Err(e) = replay_log(&tokio_plane_data_db_replay, replay, receiver).await {
+                eprintln!("Replay, error: {}", e);
+            }
+        });
+    }
+
+    // Publish decoded aircraft state to MQTT
+    for mqtt_publishers in config.clone().mqtt_publisher.into_iter() {
+        for mqtt_publisher in mqtt_publishers {
+            let tokio_plane_data_db_publish = shared_plane_data_db.clone();
             tokio::spawn(async move {
-                let _ = connect_mqtt(&tokio_plane_data_db_mqtt, mqtt_broker).await;
+                if let Err(e) = publish_mqtt(&tokio_plane_data_db_publish, mqtt_publisher).await {
+                    eprintln!("MQTT publisher, error: {}", e);
+                }
             });
         }
     }
 
+    // Re-serve the merged feed as SBS
+    if let Some(sbs_output) = config.sbs_output.clone() {
+        let tokio_plane_data_db_output = shared_plane_data_db.clone();
+        tokio::spawn(async move {
+            if let Err(e) = serve_sbs(&tokio_plane_data_db_output, sbs_output).await {
+                eprintln!("SBS output, error: {}", e);
+            }
+        });
+    }
+
+    // Serve aircraft.json and receiver.json like readsb
+    if let Some(http_api) = config.http_api.clone() {
+        let tokio_plane_data_db_http = shared_plane_data_db.clone();
+        let receiver = config.receiver.clone();
+        tokio::spawn(async move {
+            if let Err(e) = serve_http_api(&tokio_plane_data_db_http, http_api, receiver).await {
+                eprintln!("HTTP API, error: {}", e);
+            }
+        });
+    }
+
+    // Stream aircraft changes via WebSocket
+    if let Some(ws_server) = config.websocket.clone() {
+        let tokio_plane_data_db_ws = shared_plane_data_db.clone();
+        tokio::spawn(async move {
+            if let Err(e) = serve_websocket(&tokio_plane_data_db_ws, ws_server).await {
+                eprintln!("WebSocket, error: {}", e);
+            }
+        });
+    }
+
+    // Headless, e.g. at the antenna mast, viewed remotely via the output and API servers
+    if config.headless || args.iter().any(|arg| arg == "--headless") {
+        println!("Running headless, stop with Ctrl-C");
+        let _ = tokio::signal::ctrl_c().await;
+        return;
+    }
+
     // Set application name
     let app_window = Some(Window {
         title: "Luftraum".into(),
@@ -94,6 +269,7 @@ async fn main() {
         }))
         .insert_resource(ShareStruct(bevy_plane_data_db))   
         .add_plugins(setup::plugin)             // camera, basic landscape, support gizmos
+        .add_plugins(plugin_bridge::plugin)     // aircraft changes as resource and events
         .add_plugins(plugin_egui::plugin)       // egui
         .add_plugins(plugin_plane::plugin)      // plane related, setup, updates
         .add_plugins(plugin_sound::plugin)      //
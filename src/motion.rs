@@ -0,0 +1,332 @@
+// Motion model of an aircraft, smooths reported values and extrapolates between updates.
+//
+// A constant velocity Kalman filter runs per axis (east, north, up) in a local plane around the
+// first position. Positions and altitudes update the position of an axis, ground speed, track
+// and vertical rate its velocity. Between updates `MotionState::extrapolate` dead reckons from
+// the filtered position and velocity, at most for MAX_EXTRAPOLATION.
+//
+// Measurements are timed by their reception, not by the clock of the source, so messages of
+// sources with a wrong clock and MLAT results share one time base.
+
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use std::time::{Duration, Instant};
+
+const EARTH_RADIUS_M: f64 = 6_371_000.0;
+const M_PER_FT: f64 = 0.3048;
+const MPS_PER_KT: f64 = 0.514444;
+const MPS_PER_FPM: f64 = 0.00508;
+
+// Extrapolation stops after this time without updates, the aircraft is stale by then
+const MAX_EXTRAPOLATION: Duration = Duration::from_secs(20);
+// The filter starts over after a gap this long
+const MAX_GAP_SECS: f64 = 120.0;
+
+// Standard deviations of the process (acceleration) and of the measurements
+const HORIZONTAL_ACCELERATION: f64 = 3.0; // m/s², turns and speed changes
+const VERTICAL_ACCELERATION: f64 = 1.0;   // m/s²
+const POSITION_ERROR: f64 = 30.0;         // m, ADS-B
+const MLAT_POSITION_ERROR: f64 = 150.0;   // m, multilateration
+const ALTITUDE_ERROR: f64 = 15.0;         // m, 25 ft or 100 ft steps
+const VELOCITY_ERROR: f64 = 2.0;          // m/s
+const VERTICAL_RATE_ERROR: f64 = 0.5;     // m/s, 64 fpm steps
+// Velocity is unknown at first and reported once its standard deviation is below KNOWN_VELOCITY_ERROR,
+// it is estimated from positions alone as well, e.g. multilateration
+const INITIAL_VELOCITY_ERROR: f64 = 300.0; // m/s
+const KNOWN_VELOCITY_ERROR: f64 = 15.0;    // m/s
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum PositionAccuracy {
+    Adsb,
+    Mlat,
+}
+
+// Position and velocity along one axis, with covariance
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct Axis {
+    position: Option<f64>, // m
+    velocity: f64,         // m/s
+    p: [[f64; 2]; 2],      // Covariance of position and velocity
+}
+
+impl Default for Axis {
+    fn default() -> Self {
+        Axis {
+            position: None,
+            velocity: 0.0,
+            p: [[0.0, 0.0], [0.0, INITIAL_VELOCITY_ERROR * INITIAL_VELOCITY_ERROR]],
+        }
+    }
+}
+
+impl Axis {
+    fn velocity(&self) -> Option<f64> {
+        (self.p[1][1] < KNOWN_VELOCITY_ERROR * KNOWN_VELOCITY_ERROR).then_some(self.velocity)
+    }
+
+    fn predict(&mut self, dt: f64, acceleration: f64) {
+        self.position = self.position.map(|position| position + self.velocity * dt);
+        let p = self.p;
+        let q = acceleration * acceleration;
+        self.p[0][0] = p[0][0] + dt * (p[1][0] + p[0][1]) + dt * dt * p[1][1] + q * dt.powi(4) / 4.0;
+        self.p[0][1] = p[0][1] + dt * p[1][1] + q * dt.powi(3) / 2.0;
+        self.p[1][0] = p[1][0] + dt * p[1][1] + q * dt.powi(3) / 2.0;
+        self.p[1][1] = p[1][1] + q * dt * dt;
+    }
+
+    fn update_position(&mut self, measured: f64, error: f64) {
+        let Some(position) = self.position else {
+            self.position = Some(measured);
+            self.p[0][0] = error * error;
+            self.p[0][1] = 0.0;
+            self.p[1][0] = 0.0;
+            return;
+        };
+        let p = self.p;
+        let s = p[0][0] + error * error;
+        let k = [p[0][0] / s, p[1][0] / s];
+        let residual = measured - position;
+        self.position = Some(position + k[0] * residual);
+        self.velocity += k[1] * residual;
+        self.p[0][0] = (1.0 - k[0]) * p[0][0];
+        self.p[0][1] = (1.0 - k[0]) * p[0][1];
+        self.p[1][0] = p[1][0] - k[1] * p[0][0];
+        self.p[1][1] = p[1][1] - k[1] * p[0][1];
+    }
+
+    fn update_velocity(&mut self, measured: f64, error: f64) {
+        let p = self.p;
+        let s = p[1][1] + error * error;
+        let k = [p[0][1] / s, p[1][1] / s];
+        let residual = measured - self.velocity;
+        self.position = self.position.map(|position| position + k[0] * residual);
+        self.velocity += k[1] * residual;
+        self.p[0][0] = p[0][0] - k[0] * p[1][0];
+        self.p[0][1] = p[0][1] - k[0] * p[1][1];
+        self.p[1][0] = (1.0 - k[1]) * p[1][0];
+        self.p[1][1] = (1.0 - k[1]) * p[1][1];
+    }
+}
+
+// Filter of one aircraft, fed with the values of every accepted message
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct MotionFilter {
+    reference: Option<(f64, f64)>,   // Latitude and longitude of the local plane's origin
+    time: Option<DateTime<Utc>>,     // Time of the latest measurement
+    updated: Instant,                // Wall clock of the latest measurement, base of extrapolation
+    east: Axis,
+    north: Axis,
+    up: Axis,
+}
+
+impl Default for MotionFilter {
+    fn default() -> Self {
+        MotionFilter {
+            reference: None,
+            time: None,
+            updated: Instant::now(),
+            east: Axis::default(),
+            north: Axis::default(),
+            up: Axis::default(),
+        }
+    }
+}
+
+impl MotionFilter {
+    /// Advance all axes to `time`, older measurements (other sources) are applied without prediction
+    fn predict(&mut self, time: DateTime<Utc>) {
+        self.updated = Instant::now();
+        let Some(last) = self.time else {
+            self.time = Some(time);
+            return;
+        };
+        let dt = (time - last).num_milliseconds() as f64 / 1000.0;
+        if dt > MAX_GAP_SECS {
+            *self = MotionFilter::default();
+            self.time = Some(time);
+        } else if dt > 0.0 {
+            self.east.predict(dt, HORIZONTAL_ACCELERATION);
+            self.north.predict(dt, HORIZONTAL_ACCELERATION);
+            self.up.predict(dt, VERTICAL_ACCELERATION);
+            self.time = Some(time);
+        }
+    }
+
+    pub fn update_position(&mut self, time: DateTime<Utc>, latitude: f32, longitude: f32, accuracy: PositionAccuracy) {
+        self.predict(time);
+        let reference = *self.reference.get_or_insert((latitude as f64, longitude as f64));
+        let (east, north) = to_local(reference, latitude as f64, longitude as f64);
+        let error = match accuracy {
+            PositionAccuracy::Adsb => POSITION_ERROR,
+            PositionAccuracy::Mlat => MLAT_POSITION_ERROR,
+        };
+        self.east.update_position(east, error);
+        self.north.update_position(north, error);
+    }
+
+    pub fn update_altitude(&mut self, time: DateTime<Utc>, altitude: f32) {
+        self.predict(time);
+        self.up.update_position(altitude as f64 * M_PER_FT, ALTITUDE_ERROR);
+    }
+
+    pub fn update_velocity(&mut self, time: DateTime<Utc>, ground_speed: f32, track: f32) {
+        self.predict(time);
+        let speed = ground_speed as f64 * MPS_PER_KT;
+        let track = (track as f64).to_radians();
+        self.east.update_velocity(speed * track.sin(), VELOCITY_ERROR);
+        self.north.update_velocity(speed * track.cos(), VELOCITY_ERROR);
+    }
+
+    pub fn update_vertical_rate(&mut self, time: DateTime<Utc>, vertical_rate: f32) {
+        self.predict(time);
+        self.up.update_velocity(vertical_rate as f64 * MPS_PER_FPM, VERTICAL_RATE_ERROR);
+    }
+
+    /// Extrapolation continues from now, e.g. after the data has been restored from a snapshot
+    pub fn restart(&mut self) {
+        self.updated = Instant::now();
+    }
+
+    /// Filtered state at the latest measurement, None without position
+    pub fn state(&self) -> Option<MotionState> {
+        let reference = self.reference?;
+        let (latitude, longitude) = from_local(reference, self.east.position?, self.north.position?);
+        let velocity = self.east.velocity().zip(self.north.velocity());
+        Some(MotionState {
+            latitude: latitude as f32,
+            longitude: longitude as f32,
+            altitude: self.up.position.map(|up| (up / M_PER_FT) as f32),
+            ground_speed: velocity.map(|(east, north)| (east.hypot(north) / MPS_PER_KT) as f32),
+            track: velocity.map(|(east, north)| (east.atan2(north).to_degrees() as f32 + 360.0) % 360.0),
+            vertical_rate: self.up.velocity().map(|up| (up / MPS_PER_FPM) as f32),
+            time: self.time?,
+            updated: self.updated,
+        })
+    }
+}
+
+// Filtered state of an aircraft, see MotionFilter
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+pub struct MotionState {
+    pub latitude: f32,
+    pub longitude: f32,
+    pub altitude: Option<f32>,      // Feet
+    pub ground_speed: Option<f32>,  // Knots
+    pub track: Option<f32>,         // Degrees
+    pub vertical_rate: Option<f32>, // Feet per minute
+    pub time: DateTime<Utc>,        // Time of the latest measurement, or extrapolated to
+    #[serde(skip)]
+    pub updated: Instant, // Wall clock the state belongs to
+}
+
+impl MotionState {
+    /// Dead reckoning from the filtered position and velocity, `elapsed` since `updated`
+    pub fn extrapolate(&self, elapsed: Duration) -> MotionState {
+        let elapsed = elapsed.min(MAX_EXTRAPOLATION);
+        let seconds = elapsed.as_secs_f64();
+        let mut state = *self;
+        state.time += chrono::Duration::from_std(elapsed).unwrap_or_default();
+        if let (Some(ground_speed), Some(track)) = (self.ground_speed, self.track) {
+            let distance = ground_speed as f64 * MPS_PER_KT * seconds;
+            let track = (track as f64).to_radians();
+            let reference = (self.latitude as f64, self.longitude as f64);
+            let (latitude, longitude) = from_local(reference, distance * track.sin(), distance * track.cos());
+            state.latitude = latitude as f32;
+            state.longitude = longitude as f32;
+        }
+        if let (Some(altitude), Some(vertical_rate)) = (self.altitude, self.vertical_rate) {
+            state.altitude = Some(altitude + vertical_rate * seconds as f32 / 60.0);
+        }
+        state
+    }
+
+    /// Extrapolated to now
+    pub fn now(&self) -> MotionState {
+        self.extrapolate(self.updated.elapsed())
+    }
+
+    /// Angle of climb in radians, negative when descending
+    pub fn climb_angle(&self) -> f32 {
+        match (self.ground_speed, self.vertical_rate) {
+            (Some(ground_speed), Some(vertical_rate)) => {
+                ((vertical_rate as f64 * MPS_PER_FPM).atan2(ground_speed as f64 * MPS_PER_KT)) as f32
+            }
+            _ => 0.0,
+        }
+    }
+}
+
+/// East and north in meters from `reference`, equirectangular
+fn to_local(reference: (f64, f64), latitude: f64, longitude: f64) -> (f64, f64) {
+    let east = (longitude - reference.1).to_radians() * EARTH_RADIUS_M * reference.0.to_radians().cos();
+    let north = (latitude - reference.0).to_radians() * EARTH_RADIUS_M;
+    (east, north)
+}
+
+fn from_local(reference: (f64, f64), east: f64, north: f64) -> (f64, f64) {
+    let latitude = reference.0 + (north / EARTH_RADIUS_M).to_degrees();
+    let longitude = reference.1 + (east / (EARTH_RADIUS_M * reference.0.to_radians().cos())).to_degrees();
+    (latitude, longitude)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn time(milliseconds: i64) -> DateTime<Utc> {
+        DateTime::from_timestamp_millis(1_700_000_000_000 + milliseconds).unwrap()
+    }
+
+    #[test]
+    fn test_motion_filter() {
+        // Eastbound at 400 kt, climbing 1200 fpm, positions with some noise
+        let mut filter = MotionFilter::default();
+        let reference = (53.5, 10.0);
+        let speed = 400.0 * MPS_PER_KT;
+        for second in 0..30 {
+            let noise = if second % 2 == 0 { 20.0 } else { -20.0 };
+            let (latitude, longitude) = from_local(reference, speed * second as f64 + noise, noise);
+            filter.update_position(time(second * 1000), latitude as f32, longitude as f32, PositionAccuracy::Adsb);
+            filter.update_altitude(time(second * 1000), 10000.0 + 20.0 * second as f32);
+            if second % 5 == 0 {
+                filter.update_velocity(time(second * 1000), 400.0, 90.0);
+                filter.update_vertical_rate(time(second * 1000), 1200.0);
+            }
+        }
+
+        let state = filter.state().unwrap();
+        assert_eq!(state.time, time(29_000));
+        assert!((state.ground_speed.unwrap() - 400.0).abs() < 5.0);
+        assert!((state.track.unwrap() - 90.0).abs() < 1.0);
+        assert!((state.vertical_rate.unwrap() - 1200.0).abs() < 100.0);
+        let (east, north) = to_local(reference, state.latitude as f64, state.longitude as f64);
+        assert!((east - speed * 29.0).abs() < 30.0);
+        assert!(north.abs() < 30.0);
+
+        // Dead reckoning, 10 s further east
+        let ahead = state.extrapolate(Duration::from_secs(10));
+        assert_eq!(ahead.time, time(39_000));
+        let (east, _) = to_local(reference, ahead.latitude as f64, ahead.longitude as f64);
+        assert!((east - speed * 39.0).abs() < 100.0);
+        assert!((ahead.altitude.unwrap() - state.altitude.unwrap() - 200.0).abs() < 20.0);
+        assert!(ahead.climb_angle() > 0.0);
+
+        // Extrapolation is limited
+        assert_eq!(state.extrapolate(Duration::from_secs(600)), state.extrapolate(MAX_EXTRAPOLATION));
+    }
+
+    #[test]
+    fn test_motion_filter_gap() {
+        let mut filter = MotionFilter::default();
+        filter.update_velocity(time(0), 400.0, 90.0);
+        assert_eq!(filter.state(), None);
+        filter.update_position(time(1000), 53.5, 10.0, PositionAccuracy::Adsb);
+        assert!(filter.state().unwrap().ground_speed.is_some());
+
+        // Starts over after a long gap
+        filter.update_position(time(600_000), 52.0, 9.0, PositionAccuracy::Mlat);
+        let state = filter.state().unwrap();
+        assert_eq!((state.latitude, state.longitude), (52.0, 9.0));
+        assert_eq!(state.ground_speed, None);
+    }
+}
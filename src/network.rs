@@ -1,13 +1,29 @@
 use std::sync::{Arc, Mutex};
-use tokio::io::{AsyncBufReadExt, BufReader};
-use tokio::net::TcpStream;
-use tokio::time::{Duration, sleep};
-use rumqttc::{Event, Incoming, MqttOptions, AsyncClient, QoS};
+use tokio::io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader};
+use std::collections::{HashMap, HashSet};
+use chrono::Utc;
+use tokio::net::{TcpListener, TcpStream, UdpSocket};
+use tokio::sync::broadcast;
+use tokio::time::{Duration, Instant, sleep};
+use rumqttc::{Event, Incoming, LastWill, MqttOptions, AsyncClient, QoS, TlsConfiguration, Transport};
+use std::fs;
 use serde::Deserialize;
 
+use crate::beast::BeastDecoder;
 use crate::data_share::*;
-use crate::decode::decode_message_sbs;
+use crate::aircraft_json::parse_aircraft_json;
+use crate::decode::{
+    decode_aircraft_json, decode_message_avr, decode_message_beast, decode_message_sbs,
+    ModeSDecoders, PayloadDecoder, PayloadFormat,
+};
 use crate::logging::log_messages;
+use crate::source_health::{Backoff, SourceMonitor};
+
+// Delays between reconnects of TCP sources
+const RECONNECT_DELAY: Duration = Duration::from_secs(1);
+const RECONNECT_MAX_DELAY: Duration = Duration::from_secs(60);
+// Minimum timeout of a poll of aircraft.json, longer if polled less often
+const AIRCRAFT_JSON_MIN_TIMEOUT: Duration = Duration::from_secs(5);
 
 // MQTT client
 #[derive(Debug, Deserialize, Clone)]
@@ -15,10 +31,171 @@ pub struct MqttBroker {
     pub label: String,
     pub mqtt_broker_hostname: String,
     pub mqtt_broker_port: u16,
-    pub mqtt_topic: String,
+    #[serde(default)]
+    pub mqtt_topic: Option<String>, // Single topic with QoS 0, see mqtt_topics
+    #[serde(default)]
+    pub mqtt_topics: Vec<MqttTopic>,
+    pub mqtt_user: String,
+    pub mqtt_password: String,
+    pub mqtt_keepalive: u64,
+    #[serde(default)]
+    pub mqtt_client_id: Option<String>, // Defaults to "luftraum-<label>", has to be unique per broker
+    #[serde(default = "default_mqtt_clean_session")]
+    pub mqtt_clean_session: bool,
+    #[serde(default)]
+    pub mqtt_tls: Option<MqttTls>,
+    #[serde(default)]
+    pub mqtt_reconnect: MqttReconnect,
+    #[serde(default)]
+    pub payload_format: PayloadFormat, // sbs, sbs_batch, json, beast_hex or avr
+    #[serde(default = "default_raw_log")]
+    pub raw_log: bool, // Write received messages to the raw log
+}
+
+#[derive(Debug, Deserialize, Clone)]
+pub struct MqttTopic {
+    pub topic: String,
+    #[serde(default)]
+    pub qos: u8, // 0, 1 or 2
+}
+
+// TLS with a private CA, client certificate and key for brokers requiring client authentication
+#[derive(Debug, Deserialize, Clone)]
+pub struct MqttTls {
+    pub ca_file: String,                  // PEM
+    pub client_cert_file: Option<String>, // PEM
+    pub client_key_file: Option<String>,  // PEM
+}
+
+// Delay between reconnects doubles after every failure, up to max_delay_ms, randomized by +-25%
+#[derive(Debug, Deserialize, Clone)]
+pub struct MqttReconnect {
+    #[serde(default = "default_mqtt_reconnect_delay_ms")]
+    pub delay_ms: u64,
+    #[serde(default = "default_mqtt_reconnect_max_delay_ms")]
+    pub max_delay_ms: u64,
+    #[serde(default)]
+    pub max_attempts: Option<u32>, // Give up after this many failures in a row, retry forever if not set
+}
+
+impl Default for MqttReconnect {
+    fn default() -> Self {
+        MqttReconnect {
+            delay_ms: default_mqtt_reconnect_delay_ms(),
+            max_delay_ms: default_mqtt_reconnect_max_delay_ms(),
+            max_attempts: None,
+        }
+    }
+}
+
+fn default_raw_log() -> bool {
+    true
+}
+
+fn default_mqtt_clean_session() -> bool {
+    true
+}
+
+fn default_mqtt_reconnect_delay_ms() -> u64 {
+    1000
+}
+
+fn default_mqtt_reconnect_max_delay_ms() -> u64 {
+    60000
+}
+
+/// Client id as configured or derived from the label, e.g. "luftraum-<label>"
+fn mqtt_client_id(configured: &Option<String>, prefix: &str, label: &str) -> String {
+    configured.clone().unwrap_or_else(|| {
+        let label: String = label
+            .chars()
+            .map(|c| if c.is_ascii_alphanumeric() { c } else { '-' })
+            .collect();
+        format!("{}-{}", prefix, label)
+    })
+}
+
+/// TLS transport if configured, certificates are read from files
+fn mqtt_transport(tls: &Option<MqttTls>) -> Result<Transport, String> {
+    let tls = match tls {
+        Some(tls) => tls,
+        None => return Ok(Transport::Tcp),
+    };
+    let read = |path: &str| fs::read(path).map_err(|e| format!("Reading '{}', error: {}", path, e));
+    let client_auth = match (&tls.client_cert_file, &tls.client_key_file) {
+        (Some(cert), Some(key)) => Some((read(cert)?, read(key)?)),
+        (None, None) => None,
+        _ => return Err("Configuration: Client certificate and key are required both".to_string()),
+    };
+    Ok(Transport::tls_with_config(TlsConfiguration::Simple {
+        ca: read(&tls.ca_file)?,
+        alpn: None,
+        client_auth,
+    }))
+}
+
+impl MqttBroker {
+    /// Client id as configured or "luftraum-<label>"
+    pub fn client_id(&self) -> String {
+        mqtt_client_id(&self.mqtt_client_id, "luftraum", &self.label)
+    }
+
+    /// All topics with their QoS, mqtt_topic is subscribed with QoS 0
+    pub fn topics(&self) -> Result<Vec<(String, QoS)>, String> {
+        let mut topics = vec![];
+        if let Some(topic) = &self.mqtt_topic {
+            topics.push((topic.clone(), QoS::AtMostOnce));
+        }
+        for topic in self.mqtt_topics.iter() {
+            let qos = match topic.qos {
+                0 => QoS::AtMostOnce,
+                1 => QoS::AtLeastOnce,
+                2 => QoS::ExactlyOnce,
+                qos => return Err(format!("Configuration: QoS {} of topic '{}' out of range (0-2)", qos, topic.topic)),
+            };
+            topics.push((topic.topic.clone(), qos));
+        }
+        if topics.is_empty() {
+            return Err("Configuration: No MQTT topic".to_string());
+        }
+        Ok(topics)
+    }
+
+    fn transport(&self) -> Result<Transport, String> {
+        mqtt_transport(&self.mqtt_tls)
+    }
+}
+
+// MQTT publisher of decoded aircraft state
+#[derive(Debug, Deserialize, Clone)]
+pub struct MqttPublisher {
+    pub label: String,
+    pub mqtt_broker_hostname: String,
+    pub mqtt_broker_port: u16,
+    #[serde(default = "default_mqtt_topic_prefix")]
+    pub mqtt_topic_prefix: String, // <prefix>/aircraft/<hex> and <prefix>/status
     pub mqtt_user: String,
     pub mqtt_password: String,
     pub mqtt_keepalive: u64,
+    #[serde(default)]
+    pub mqtt_client_id: Option<String>, // Defaults to "luftraum-publisher-<label>", has to be unique per broker
+    #[serde(default)]
+    pub mqtt_tls: Option<MqttTls>,
+}
+
+fn default_mqtt_topic_prefix() -> String {
+    "luftraum".to_string()
+}
+
+impl MqttPublisher {
+    /// Client id as configured or "luftraum-publisher-<label>", distinct from subscribers of the same label
+    pub fn client_id(&self) -> String {
+        mqtt_client_id(&self.mqtt_client_id, "luftraum-publisher", &self.label)
+    }
+
+    fn transport(&self) -> Result<Transport, String> {
+        mqtt_transport(&self.mqtt_tls)
+    }
 }
 
 #[derive(Debug, Deserialize, Clone)]
@@ -26,6 +203,97 @@ pub struct SbsServer {
     pub label: String,
     pub sbs_hostname: String,
     pub sbs_port: u32,
+    #[serde(default = "default_raw_log")]
+    pub raw_log: bool, // Write received messages to the raw log
+}
+
+// Receiver (antenna) position, reference for local CPR decoding of raw frames
+#[derive(Debug, Deserialize, Clone)]
+pub struct Receiver {
+    pub latitude: f64,
+    pub longitude: f64,
+}
+
+impl Receiver {
+    pub fn position(&self) -> (f64, f64) {
+        (self.latitude, self.longitude)
+    }
+}
+
+#[derive(Debug, Deserialize, Clone)]
+pub struct BeastServer {
+    pub label: String,
+    pub beast_hostname: String,
+    pub beast_port: u32,
+    #[serde(default = "default_raw_log")]
+    pub raw_log: bool, // Write received messages to the raw log
+}
+
+#[derive(Debug, Deserialize, Clone)]
+pub struct AvrServer {
+    pub label: String,
+    pub avr_hostname: String,
+    pub avr_port: u32,
+    #[serde(default = "default_raw_log")]
+    pub raw_log: bool, // Write received messages to the raw log
+}
+
+// aircraft.json of readsb/tar1090, polled from an HTTP URL or a local file
+#[derive(Debug, Deserialize, Clone)]
+pub struct AircraftJsonSource {
+    pub label: String,
+    pub url: String, // http(s)://... or a file path
+    #[serde(default = "default_poll_interval_ms")]
+    pub poll_interval_ms: u64,
+}
+
+fn default_poll_interval_ms() -> u64 {
+    1000
+}
+
+// UDP listener for feeders pushing datagrams, e.g. over LTE links
+#[derive(Debug, Deserialize, Clone)]
+pub struct UdpListener {
+    pub label: String,
+    #[serde(default = "default_udp_bind_address")]
+    pub udp_bind_address: String,
+    pub udp_port: u16,
+    #[serde(default)]
+    pub payload_format: PayloadFormat, // sbs, sbs_batch, json, beast_hex, avr or mode_s
+    #[serde(default = "default_raw_log")]
+    pub raw_log: bool, // Write received messages to the raw log
+}
+
+fn default_udp_bind_address() -> String {
+    "0.0.0.0".to_string()
+}
+
+// SBS output server, re-serves the merged feed, e.g. for Virtual Radar Server
+#[derive(Debug, Deserialize, Clone)]
+pub struct SbsOutput {
+    #[serde(default = "default_sbs_output_bind_address")]
+    pub sbs_output_bind_address: String,
+    pub sbs_output_port: u16,
+    #[serde(default)]
+    pub mode: SbsOutputMode,
+    #[serde(default = "default_sbs_output_interval_ms")]
+    pub interval_ms: u64, // Only used by normalized mode
+}
+
+fn default_sbs_output_bind_address() -> String {
+    "0.0.0.0".to_string()
+}
+
+fn default_sbs_output_interval_ms() -> u64 {
+    1000
+}
+
+#[derive(Debug, Deserialize, Clone, Copy, Default, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum SbsOutputMode {
+    #[default]
+    Passthrough, // Every accepted message of all sources as SBS line
+    Normalized,  // Merged state of all aircraft every interval
 }
 
 impl SbsServer {
@@ -44,17 +312,22 @@ pub async fn connect_dump1090_sbs(
     // Define the address and port where dump1090 is serving data and connect to server
     let addr = format!("{}:{}", sbs_server.sbs_hostname, sbs_server.sbs_port);
 
+    let monitor = SourceMonitor::new(&sbs_server.label, "sbs");
+    let mut backoff = Backoff::new(RECONNECT_DELAY, RECONNECT_MAX_DELAY);
+
     loop {
         // Reconnect till connected
         let stream = loop {
+            monitor.connecting();
             match TcpStream::connect(&addr).await {
                 Ok(stream) => break stream,
                 Err(e) => {
-                    eprintln!("Connection to {}, error: {}", addr, e);
-                    sleep(Duration::from_secs(5)).await;
+                    monitor.failed(&format!("Connection to {}, error: {}", addr, e));
+                    sleep(backoff.next_delay()).await;
                 }
             }
         };
+        monitor.connected();
 
         // TODO: Log connection information or screen/egui indicator?
         println!("Connected to dump1090 at {}", addr);
@@ -70,16 +343,24 @@ pub async fn connect_dump1090_sbs(
             match line {
                 Ok(Some(message)) => {
                     // Log everything to file by now, message is the raw data set
-                    let _ = log_messages("sbs", &message);
-                    // Decode message and store it in struct
-                    let _ = decode_message_sbs(data_share, message);
+                    monitor.received(1, message.len() + 1);
+                    if sbs_server.raw_log {
+                        log_messages("sbs", &message);
+                    }
+                    // Decode message and store it in struct, failures are counted
+                    // A server that accepts and closes right away keeps backing off
+                    if decode_message_sbs(data_share, &sbs_server.label, message, None).is_ok() {
+                        backoff.reset();
+                    }
                 }
                 Err(e) => {
-                    eprintln!("Error reading line. Error: {:?}", e);
+                    monitor.failed(&format!("Error reading line. Error: {:?}", e));
+                    sleep(backoff.next_delay()).await;
+                    break 'read;
                 }
                 _ => {
-                    eprintln!("Connection lost.");
-                    sleep(Duration::from_secs(5)).await;
+                    monitor.failed("Connection lost");
+                    sleep(backoff.next_delay()).await;
                     break 'read;
                 }
             }
@@ -89,52 +370,511 @@ pub async fn connect_dump1090_sbs(
     Ok(())
 }
 
-/// Connect to MQTT broker to pull ADS-B messages
-pub async fn connect_mqtt(data_share: &Arc<Mutex<SharedDataDb>>, mqtt_broker: MqttBroker
+/// Connect to a Beast binary feed, e.g. dump1090 --net-bo-port 30005
+pub async fn connect_dump1090_beast(
+    data_share: &Arc<Mutex<SharedDataDb>>, beast_server: BeastServer, receiver: Option<Receiver>,
+) -> Result<(), Box<dyn std::error::Error>> {
+
+    let addr = format!("{}:{}", beast_server.beast_hostname, beast_server.beast_port);
+
+    let monitor = SourceMonitor::new(&beast_server.label, "beast");
+    let mut backoff = Backoff::new(RECONNECT_DELAY, RECONNECT_MAX_DELAY);
+
+    loop {
+        // Reconnect till connected
+        let mut stream = loop {
+            monitor.connecting();
+            match TcpStream::connect(&addr).await {
+                Ok(stream) => break stream,
+                Err(e) => {
+                    monitor.failed(&format!("Connection to {}, error: {}", addr, e));
+                    sleep(backoff.next_delay()).await;
+                }
+            }
+        };
+        monitor.connected();
+
+        println!("Connected to Beast feed at {}", addr);
+
+        // Frames may be split across reads, the decoder keeps the remainder
+        let mut decoder = BeastDecoder::new();
+        let mut mode_s_decoders = ModeSDecoders::new(receiver.as_ref().map(|r| r.position()));
+        let mut buffer = [0u8; 4096];
+
+        'read: loop {
+
+            match stream.read(&mut buffer).await {
+                Ok(0) => {
+                    monitor.failed("Connection lost");
+                    sleep(backoff.next_delay()).await;
+                    break 'read;
+                }
+                Ok(len) => {
+                    decoder.push(&buffer[..len]);
+                    monitor.received(0, len);
+                    while let Some(frame) = decoder.next_frame() {
+                        monitor.received(1, 0);
+                        // A server that accepts and closes right away keeps backing off
+                        backoff.reset();
+                        // Log everything to file by now, timestamp and signal level in front of the frame
+                        if beast_server.raw_log {
+                            log_messages("beast", &frame.to_log());
+                        }
+                        decode_message_beast(data_share, &beast_server.label, &mut mode_s_decoders, &frame);
+                    }
+                }
+                Err(e) => {
+                    monitor.failed(&format!("Error reading Beast data. Error: {:?}", e));
+                    sleep(backoff.next_delay()).await;
+                    break 'read;
+                }
+            }
+        }
+    }
+}
+
+/// Connect to an AVR raw hex feed, e.g. dump1090 --net-ro-port 30002
+pub async fn connect_dump1090_avr(
+    data_share: &Arc<Mutex<SharedDataDb>>, avr_server: AvrServer, receiver: Option<Receiver>,
 ) -> Result<(), Box<dyn std::error::Error>> {
-    
-    let broker = mqtt_broker.mqtt_broker_hostname;
-    let port = mqtt_broker.mqtt_broker_port;
-    let keepalive = mqtt_broker.mqtt_keepalive; // Seconds
-    let topic = mqtt_broker.mqtt_topic;
-    let username = mqtt_broker.mqtt_user;
-    let password = mqtt_broker.mqtt_password;
+
+    let addr = format!("{}:{}", avr_server.avr_hostname, avr_server.avr_port);
+
+    let monitor = SourceMonitor::new(&avr_server.label, "avr");
+    let mut backoff = Backoff::new(RECONNECT_DELAY, RECONNECT_MAX_DELAY);
+
+    loop {
+        // Reconnect till connected
+        let stream = loop {
+            monitor.connecting();
+            match TcpStream::connect(&addr).await {
+                Ok(stream) => break stream,
+                Err(e) => {
+                    monitor.failed(&format!("Connection to {}, error: {}", addr, e));
+                    sleep(backoff.next_delay()).await;
+                }
+            }
+        };
+        monitor.connected();
+
+        println!("Connected to AVR feed at {}", addr);
+
+        let reader = BufReader::new(stream);
+        let mut lines = reader.lines();
+        let mut mode_s_decoders = ModeSDecoders::new(receiver.as_ref().map(|r| r.position()));
+
+        'read: loop {
+
+            let line = lines.next_line().await;
+            match line {
+                Ok(Some(message)) => {
+                    // Log everything to file by now, message is the raw data set
+                    monitor.received(1, message.len() + 1);
+                    if avr_server.raw_log {
+                        log_messages("avr", &message);
+                    }
+                    // A server that accepts and closes right away keeps backing off
+                    if decode_message_avr(data_share, &avr_server.label, &mut mode_s_decoders, &message) {
+                        backoff.reset();
+                    }
+                }
+                Err(e) => {
+                    monitor.failed(&format!("Error reading line. Error: {:?}", e));
+                    sleep(backoff.next_delay()).await;
+                    break 'read;
+                }
+                _ => {
+                    monitor.failed("Connection lost");
+                    sleep(backoff.next_delay()).await;
+                    break 'read;
+                }
+            }
+        }
+    }
+}
+
+/// Poll aircraft.json of readsb/dump1090-fa, e.g. http://raspberrypi/tar1090/data/aircraft.json
+pub async fn poll_aircraft_json(
+    data_share: &Arc<Mutex<SharedDataDb>>, source: AircraftJsonSource,
+) -> Result<(), Box<dyn std::error::Error>> {
+
+    let interval = Duration::from_millis(source.poll_interval_ms.max(100));
+    let is_http = source.url.starts_with("http://") || source.url.starts_with("https://");
+    println!("Polling aircraft.json from {}", source.url);
+    let monitor = SourceMonitor::new(&source.label, "aircraft.json");
+    let mut backoff = Backoff::new(interval, RECONNECT_MAX_DELAY);
+    let mut last_success: Option<Instant> = None;
+    let mut last_messages: HashMap<String, u64> = HashMap::new(); // Message counter of every aircraft
+    // A station that accepts and then hangs fails the poll instead of stalling the source
+    let client = reqwest::Client::builder()
+        .timeout(interval.max(AIRCRAFT_JSON_MIN_TIMEOUT))
+        .build()?;
+
+    loop {
+        let body = if is_http {
+            match client.get(&source.url).send().await.and_then(|response| response.error_for_status()) {
+                Ok(response) => response.text().await.map_err(|e| e.to_string()),
+                Err(e) => Err(e.to_string()),
+            }
+        } else {
+            tokio::fs::read_to_string(&source.url)
+                .await
+                .map_err(|e| e.to_string())
+        };
+
+        let result = body.map(|body| (body.len(), parse_aircraft_json(&body)));
+        match result {
+            Ok((len, Ok(aircraft_json))) => {
+                monitor.connected();
+                monitor.received(aircraft_json.aircraft.len(), len);
+                backoff.reset();
+                // Polls jitter and may fail, data seen since twice the time of the last successful poll is
+                // stored. Aircraft without new messages since the last poll are skipped, nothing is stored twice
+                let since = last_success.map_or(interval, |last_success| last_success.elapsed().max(interval));
+                let max_age = 2.0 * since.as_secs_f32();
+                last_success = Some(Instant::now());
+                let mut messages = HashMap::new();
+                for aircraft in aircraft_json.aircraft.iter() {
+                    let hex_ident = aircraft.hex_ident();
+                    if let Some(count) = aircraft.messages {
+                        messages.insert(hex_ident.clone(), count);
+                        if last_messages.get(&hex_ident) == Some(&count) {
+                            continue;
+                        }
+                    }
+                    decode_aircraft_json(data_share, &source.label, aircraft, max_age, None);
+                }
+                last_messages = messages;
+                sleep(interval).await;
+            }
+            Ok((_, Err(e))) => {
+                monitor.failed(&format!("Error parsing aircraft.json from {}, error: {}", source.url, e));
+                sleep(backoff.next_delay()).await;
+                monitor.connecting();
+            }
+            Err(e) => {
+                monitor.failed(&format!("Error reading aircraft.json from {}, error: {}", source.url, e));
+                sleep(backoff.next_delay()).await;
+                monitor.connecting();
+            }
+        }
+    }
+}
+
+// UDP senders idle this long are forgotten, e.g. feeders on mobile networks change their address
+const UDP_SENDER_MAX_IDLE: Duration = Duration::from_secs(600);
+const UDP_SENDER_PRUNE_INTERVAL: Duration = Duration::from_secs(60);
+
+/// Listen for datagrams with SBS lines or raw frames, counts every sender separately
+pub async fn listen_udp(
+    data_share: &Arc<Mutex<SharedDataDb>>, udp_listener: UdpListener, receiver: Option<Receiver>,
+) -> Result<(), Box<dyn std::error::Error>> {
+
+    let addr = format!("{}:{}", udp_listener.udp_bind_address, udp_listener.udp_port);
+    let monitor = SourceMonitor::new(&udp_listener.label, "udp");
+    let socket = match UdpSocket::bind(&addr).await {
+        Ok(socket) => socket,
+        Err(e) => {
+            monitor.failed(&format!("Binding {}, error: {}", addr, e));
+            return Err(e.into());
+        }
+    };
+    monitor.connected();
+    println!("Listening for UDP datagrams on {}", addr);
+
+    // Every sender gets its own decoder, Beast streams must not be mixed
+    let mut payload_decoders: HashMap<std::net::SocketAddr, (PayloadDecoder, Instant)> = HashMap::new();
+    let mut buffer = [0u8; 65535];
+    let mut last_pruned = Instant::now();
+
+    loop {
+        match socket.recv_from(&mut buffer).await {
+            Ok((len, sender)) => {
+                let payload = &buffer[..len];
+                monitor.received(1, len);
+                if udp_listener.raw_log {
+                    // Binary frames are logged as AVR lines, replayed like those of an AVR feed
+                    let message = if udp_listener.payload_format == PayloadFormat::ModeS {
+                        format!("*{};", payload.iter().map(|byte| format!("{:02X}", byte)).collect::<String>())
+                    } else {
+                        String::from_utf8_lossy(payload).to_string()
+                    };
+                    log_messages(&format!("udp {}", sender), &message);
+                }
+
+                let (payload_decoder, last_received) = payload_decoders.entry(sender).or_insert_with(|| {
+                    let payload_decoder = PayloadDecoder::new(
+                        &udp_listener.label,
+                        udp_listener.payload_format,
+                        receiver.as_ref().map(|r| r.position()),
+                    );
+                    (payload_decoder, Instant::now())
+                });
+                *last_received = Instant::now();
+                let dropped = payload_decoder.decode(data_share, payload);
+
+                if let Ok(mut senders) = UDP_SENDERS.lock() {
+                    let stats = senders.entry(sender).or_insert_with(|| UdpSenderStats {
+                        listener: udp_listener.label.clone(),
+                        datagrams: 0,
+                        bytes: 0,
+                        dropped: 0,
+                        last_seen: Utc::now(),
+                    });
+                    stats.datagrams += 1;
+                    stats.bytes += len;
+                    stats.dropped += dropped;
+                    stats.last_seen = Utc::now();
+                }
+
+                // Forget idle senders
+                if last_pruned.elapsed() >= UDP_SENDER_PRUNE_INTERVAL {
+                    last_pruned = Instant::now();
+                    payload_decoders.retain(|_, (_, last_received)| last_received.elapsed() < UDP_SENDER_MAX_IDLE);
+                    if let Ok(mut senders) = UDP_SENDERS.lock() {
+                        senders.retain(|sender, stats| {
+                            stats.listener != udp_listener.label || payload_decoders.contains_key(sender)
+                        });
+                    }
+                }
+            }
+            Err(e) => {
+                monitor.failed(&format!("Error receiving UDP datagram on {}. Error: {:?}", addr, e));
+            }
+        }
+    }
+}
+
+/// Serve the merged feed as BaseStation SBS lines to any number of clients
+pub async fn serve_sbs(
+    data_share: &Arc<Mutex<SharedDataDb>>, sbs_output: SbsOutput,
+) -> Result<(), Box<dyn std::error::Error>> {
+
+    let addr = format!("{}:{}", sbs_output.sbs_output_bind_address, sbs_output.sbs_output_port);
+    let listener = TcpListener::bind(&addr).await?;
+    println!("Serving SBS ({:?}) on {}", sbs_output.mode, addr);
+
+    // Clients subscribe to a broadcast channel, sending never waits for slow clients
+    let lines = match sbs_output.mode {
+        SbsOutputMode::Passthrough => data_share.lock().unwrap().get_sbs_output(),
+        SbsOutputMode::Normalized => {
+            let (lines, _) = broadcast::channel(4096);
+            let sender = lines.clone();
+            let data_share = data_share.clone();
+            let interval = Duration::from_millis(sbs_output.interval_ms.max(100));
+            tokio::spawn(async move {
+                loop {
+                    sleep(interval).await;
+                    if sender.receiver_count() == 0 {
+                        continue;
+                    }
+                    let snapshot = data_share.lock().unwrap().get_sbs_snapshot();
+                    for message in snapshot {
+                        let _ = sender.send(message.to_line());
+                    }
+                }
+            });
+            lines
+        }
+    };
+
+    loop {
+        let (mut stream, client) = match listener.accept().await {
+            Ok(accepted) => accepted,
+            Err(e) => {
+                eprintln!("Error accepting SBS client on {}. Error: {:?}", addr, e);
+                continue;
+            }
+        };
+        println!("SBS client {} connected", client);
+
+        let mut client_lines = lines.subscribe();
+        let data_share = data_share.clone();
+        tokio::spawn(async move {
+            loop {
+                match client_lines.recv().await {
+                    Ok(line) => {
+                        if stream.write_all(format!("{}\r\n", line).as_bytes()).await.is_err() {
+                            break;
+                        }
+                    }
+                    Err(broadcast::error::RecvError::Lagged(skipped)) => {
+                        // Client too slow, the oldest lines are gone
+                        data_share
+                            .lock()
+                            .unwrap()
+                            .count_dropped_many("sbs output: slow client", skipped as usize);
+                    }
+                    Err(broadcast::error::RecvError::Closed) => break,
+                }
+            }
+            println!("SBS client {} disconnected", client);
+        });
+    }
+}
+
+/// Connect to MQTT broker to pull ADS-B messages, reconnects according to mqtt_reconnect
+pub async fn connect_mqtt(data_share: &Arc<Mutex<SharedDataDb>>, mqtt_broker: MqttBroker, receiver: Option<Receiver>,
+) -> Result<(), Box<dyn std::error::Error>> {
+
+    let topics = mqtt_broker.topics()?;
+    let reconnect = mqtt_broker.mqtt_reconnect.clone();
     println!("Label: {}", mqtt_broker.label);
-    let mut mqtt_options = MqttOptions::new("rumqtt-async", broker, port);
-    mqtt_options.set_credentials(username, password);
-    mqtt_options.set_keep_alive(Duration::from_secs(keepalive));
+    let mut mqtt_options = MqttOptions::new(
+        mqtt_broker.client_id(),
+        mqtt_broker.mqtt_broker_hostname.clone(),
+        mqtt_broker.mqtt_broker_port,
+    );
+    mqtt_options.set_credentials(mqtt_broker.mqtt_user.clone(), mqtt_broker.mqtt_password.clone());
+    mqtt_options.set_keep_alive(Duration::from_secs(mqtt_broker.mqtt_keepalive));
+    mqtt_options.set_clean_session(mqtt_broker.mqtt_clean_session);
+    mqtt_options.set_transport(mqtt_broker.transport()?);
 
     let (client, mut eventloop) = AsyncClient::new(mqtt_options, 10);
-    client.subscribe(topic, QoS::AtMostOnce).await.unwrap();
 
+    let mut payload_decoder = PayloadDecoder::new(
+        &mqtt_broker.label,
+        mqtt_broker.payload_format,
+        receiver.as_ref().map(|r| r.position()),
+    );
+
+    let monitor = SourceMonitor::new(&mqtt_broker.label, "mqtt");
+    let mut backoff = Backoff::new(
+        Duration::from_millis(reconnect.delay_ms),
+        Duration::from_millis(reconnect.max_delay_ms),
+    );
     loop {
 
         match eventloop.poll().await {
+            Ok(Event::Incoming(Incoming::ConnAck(connack))) => {
+                println!("Connected to MQTT broker {}", mqtt_broker.label);
+                monitor.connected();
+                backoff.reset();
+                // Subscriptions of a resumed session are still known by the broker
+                if !connack.session_present {
+                    for (topic, qos) in topics.iter() {
+                        // The event loop sends the request, awaiting here could block it
+                        if let Err(e) = client.try_subscribe(topic.clone(), *qos) {
+                            eprintln!("Subscribing to {}, error: {:?}", topic, e);
+                        }
+                    }
+                }
+            }
             Ok(Event::Incoming(Incoming::Publish(p))) => {
                 // println!("Topic: {}, Payload: {:?}", p.topic, p.payload);
-                let message = str::from_utf8(p.payload.as_ref());
-                match message {
-                Ok(message) => {
-                    // Log everything to file by now, message is the raw data set
-                    let _ = log_messages(&p.topic, &message);
-                    // Decode message and store it in struct
-                    let _ = decode_message_sbs(data_share, message.parse().unwrap());
+                // Log everything to file by now, message is the raw data set
+                monitor.received(1, p.payload.len());
+                if mqtt_broker.raw_log {
+                    log_messages(&p.topic, &String::from_utf8_lossy(p.payload.as_ref()));
+                }
+                // Decode message and store it in struct, failures are counted
+                payload_decoder.decode(data_share, p.payload.as_ref());
+            }
+            // Acks, pings and outgoing requests
+            Ok(_) => {}
+            Err(e) => {
+                monitor.failed(&format!("{e:?}"));
+                let delay = backoff.next_delay();
+                if reconnect.max_attempts.is_some_and(|max| backoff.attempts() >= max) {
+                    return Err(format!("MQTT broker {}: giving up after {} attempts", mqtt_broker.label, backoff.attempts()).into());
                 }
+                // rumqttc reconnects on the next poll
+                sleep(delay).await;
+                monitor.connecting();
+            }
+        }
+    }
+}
+
+/// Publish the state of every changed aircraft retained to <prefix>/aircraft/<hex>
+pub async fn publish_mqtt(data_share: &Arc<Mutex<SharedDataDb>>, mqtt_publisher: MqttPublisher,
+) -> Result<(), Box<dyn std::error::Error>> {
+
+    let prefix = mqtt_publisher.mqtt_topic_prefix.trim_end_matches('/').to_string();
+    let status_topic = format!("{}/status", prefix);
+    println!("Label: {}", mqtt_publisher.label);
+    let mut mqtt_options = MqttOptions::new(
+        mqtt_publisher.client_id(),
+        mqtt_publisher.mqtt_broker_hostname.clone(),
+        mqtt_publisher.mqtt_broker_port,
+    );
+    mqtt_options.set_credentials(mqtt_publisher.mqtt_user.clone(), mqtt_publisher.mqtt_password.clone());
+    mqtt_options.set_keep_alive(Duration::from_secs(mqtt_publisher.mqtt_keepalive));
+    mqtt_options.set_transport(mqtt_publisher.transport()?);
+    // The broker marks luftraum offline if the connection breaks
+    mqtt_options.set_last_will(LastWill::new(&status_topic, "offline", QoS::AtLeastOnce, true));
+
+    let (client, mut eventloop) = AsyncClient::new(mqtt_options, 100);
+
+    // Drive the connection, rumqttc reconnects on the next poll after an error
+    let status_client = client.clone();
+    tokio::spawn(async move {
+        loop {
+            match eventloop.poll().await {
+                Ok(Event::Incoming(Incoming::ConnAck(_))) => {
+                    println!("Connected to MQTT broker, publishing to {}", status_topic);
+                    // The event loop sends the request, awaiting here could block it
+                    if let Err(e) = status_client.try_publish(&status_topic, QoS::AtLeastOnce, true, "online") {
+                        eprintln!("Publishing to {}, error: {:?}", status_topic, e);
+                    }
+                }
+                Ok(_) => {}
                 Err(e) => {
-                    eprintln!("Error reading message. Error: {:?}", e);}
+                    eprintln!("MQTT publisher, error: {:?}", e);
+                    sleep(Duration::from_secs(5)).await;
                 }
+            }
+        }
+    });
 
+    // Retained topics on the broker, cleared again when the aircraft is gone
+    let mut published: HashSet<String> = HashSet::new();
+    let mut changes = data_share.lock().unwrap().subscribe_changes();
+    loop {
+        let change = match changes.recv().await {
+            Ok(change) => change,
+            Err(broadcast::error::RecvError::Lagged(skipped)) => {
+                // Removals may be among the skipped changes, start over with all current states
+                eprintln!("MQTT publisher lagging, {} changes skipped, publishing all aircraft", skipped);
+                let states = data_share.lock().unwrap().get_aircraft_states();
+                let current: HashSet<String> = states.iter().map(|state| state.hex_ident.clone()).collect();
+                for hex_ident in published.difference(&current) {
+                    let topic = format!("{}/aircraft/{}", prefix, hex_ident);
+                    if let Err(e) = client.publish(&topic, QoS::AtLeastOnce, true, Vec::new()).await {
+                        eprintln!("Publishing to {}, error: {:?}", topic, e);
+                    }
+                }
+                for state in states {
+                    let topic = format!("{}/aircraft/{}", prefix, state.hex_ident);
+                    let payload = serde_json::to_vec(&state)?;
+                    if let Err(e) = client.publish(&topic, QoS::AtMostOnce, true, payload).await {
+                        eprintln!("Publishing to {}, error: {:?}", topic, e);
+                    }
+                }
+                published = current;
+                continue;
             }
-            Ok(Event::Incoming(i)) => {
-                println!("Incoming = {i:?}");
+            Err(broadcast::error::RecvError::Closed) => return Ok(()),
+        };
+
+        let hex_ident = change.hex_ident().to_string();
+        let topic = format!("{}/aircraft/{}", prefix, hex_ident);
+        let state = data_share.lock().unwrap().get_aircraft_state(hex_ident.clone());
+        let result = match (change, state) {
+            // An empty retained message clears the topic on the broker
+            (AircraftChange::Removed(_), _) | (_, None) => {
+                published.remove(&hex_ident);
+                client.publish(&topic, QoS::AtLeastOnce, true, Vec::new()).await
             }
-            Ok(Event::Outgoing(o)) => println!("Outgoing {:?}", o),    // Subscribe is in here as well ...
-            Err(e) => {
-                println!("Error = {e:?}");
-                return Ok(());
+            (_, Some(state)) => {
+                published.insert(hex_ident);
+                let payload = serde_json::to_vec(&state)?;
+                client.publish(&topic, QoS::AtMostOnce, true, payload).await
             }
+        };
+        // A failed publish does not end the publisher, the next change is published again
+        if let Err(e) = result {
+            eprintln!("Publishing to {}, error: {:?}", topic, e);
         }
     }
-
-    Ok(())
-}
\ No newline at end of file
+}
@@ -0,0 +1,200 @@
+// Plausibility of positions and altitudes, checked before samples enter SharedDataDb.
+//
+// A position is rejected when the ground speed implied by the last accepted position is too
+// high or when it is beyond the range of the receiver, an altitude when the implied vertical
+// rate is too high. After MAX_REJECTED_IN_ROW rejections in a row the last accepted value is
+// considered the outlier instead, e.g. the first position of an aircraft was already wrong.
+
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+
+use crate::math::haversine_distance;
+
+// Consecutive rejections after which the new value is accepted as reference
+const MAX_REJECTED_IN_ROW: usize = 5;
+// Allowed deviation independent of the time between two samples, e.g. clocks of several sources
+const POSITION_TOLERANCE_KM: f32 = 1.0;
+const ALTITUDE_TOLERANCE_FT: f32 = 500.0;
+const KM_PER_NM: f32 = 1.852;
+
+#[derive(Debug, Deserialize, Clone, Copy, PartialEq)]
+pub struct Plausibility {
+    #[serde(default = "default_plausibility_enabled")]
+    pub enabled: bool,
+    #[serde(default = "default_max_ground_speed_kt")]
+    pub max_ground_speed_kt: f32, // Implied by two positions
+    #[serde(default = "default_max_vertical_rate_fpm")]
+    pub max_vertical_rate_fpm: f32, // Implied by two altitudes
+    #[serde(default = "default_max_range_km")]
+    pub max_range_km: f32, // Distance to the receiver, only checked if [receiver] is set
+}
+
+impl Default for Plausibility {
+    fn default() -> Self {
+        Plausibility {
+            enabled: default_plausibility_enabled(),
+            max_ground_speed_kt: default_max_ground_speed_kt(),
+            max_vertical_rate_fpm: default_max_vertical_rate_fpm(),
+            max_range_km: default_max_range_km(),
+        }
+    }
+}
+
+fn default_plausibility_enabled() -> bool {
+    true
+}
+
+fn default_max_ground_speed_kt() -> f32 {
+    1000.0
+}
+
+fn default_max_vertical_rate_fpm() -> f32 {
+    12000.0
+}
+
+fn default_max_range_km() -> f32 {
+    500.0
+}
+
+// Reason a position or altitude has been rejected
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Rejection {
+    GroundSpeed,  // Position too far from the previous one
+    VerticalRate, // Altitude too far from the previous one
+    Range,        // Position beyond the range of the receiver
+}
+
+impl Rejection {
+    pub fn description(&self) -> &'static str {
+        match self {
+            Rejection::GroundSpeed => "implied ground speed too high",
+            Rejection::VerticalRate => "implied vertical rate too high",
+            Rejection::Range => "beyond receiver range",
+        }
+    }
+}
+
+// Last accepted position and altitude of an aircraft
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct PlausibilityState {
+    position: Option<(DateTime<Utc>, f32, f32)>, // Time, latitude, longitude
+    altitude: Option<(DateTime<Utc>, f32)>,      // Time, feet
+    positions_rejected: usize,                   // In a row
+    altitudes_rejected: usize,                   // In a row
+}
+
+/// Seconds between two samples, samples of several sources may arrive out of order
+fn seconds_between(a: DateTime<Utc>, b: DateTime<Utc>) -> f32 {
+    (b - a).num_milliseconds().abs() as f32 / 1000.0
+}
+
+impl Plausibility {
+    /// Check a position, accepted positions become the reference of the next check
+    pub fn check_position(
+        &self,
+        state: &mut PlausibilityState,
+        time: DateTime<Utc>,
+        latitude: f32,
+        longitude: f32,
+        receiver: Option<(f32, f32)>,
+    ) -> Result<(), Rejection> {
+        if !self.enabled {
+            return Ok(());
+        }
+        // Range is absolute, never becomes a reference
+        if let Some(receiver) = receiver
+            && haversine_distance(receiver.0, receiver.1, latitude, longitude) > self.max_range_km
+        {
+            return Err(Rejection::Range);
+        }
+        if let Some((last_time, last_latitude, last_longitude)) = state.position
+            && state.positions_rejected < MAX_REJECTED_IN_ROW
+        {
+            let distance = haversine_distance(last_latitude, last_longitude, latitude, longitude);
+            let hours = seconds_between(last_time, time) / 3600.0;
+            if distance > self.max_ground_speed_kt * KM_PER_NM * hours + POSITION_TOLERANCE_KM {
+                state.positions_rejected += 1;
+                return Err(Rejection::GroundSpeed);
+            }
+        }
+        state.position = Some((time, latitude, longitude));
+        state.positions_rejected = 0;
+        Ok(())
+    }
+
+    /// Check an altitude, accepted altitudes become the reference of the next check
+    pub fn check_altitude(
+        &self,
+        state: &mut PlausibilityState,
+        time: DateTime<Utc>,
+        altitude: f32,
+    ) -> Result<(), Rejection> {
+        if !self.enabled {
+            return Ok(());
+        }
+        if let Some((last_time, last_altitude)) = state.altitude
+            && state.altitudes_rejected < MAX_REJECTED_IN_ROW
+        {
+            let minutes = seconds_between(last_time, time) / 60.0;
+            if (altitude - last_altitude).abs() > self.max_vertical_rate_fpm * minutes + ALTITUDE_TOLERANCE_FT {
+                state.altitudes_rejected += 1;
+                return Err(Rejection::VerticalRate);
+            }
+        }
+        state.altitude = Some((time, altitude));
+        state.altitudes_rejected = 0;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn time(seconds: i64) -> DateTime<Utc> {
+        DateTime::from_timestamp(1700000000 + seconds, 0).unwrap()
+    }
+
+    #[test]
+    fn test_check_position() {
+        let plausibility = Plausibility::default();
+        let mut state = PlausibilityState::default();
+        let receiver = Some((53.57, 9.98));
+        assert_eq!(plausibility.check_position(&mut state, time(0), 53.6, 10.0, receiver), Ok(()));
+        // About 4 km in 10 s, 780 kt
+        assert_eq!(plausibility.check_position(&mut state, time(10), 53.6, 10.06, receiver), Ok(()));
+        // About 100 km in 1 s
+        assert_eq!(
+            plausibility.check_position(&mut state, time(11), 54.5, 10.06, receiver),
+            Err(Rejection::GroundSpeed)
+        );
+        // Madrid
+        assert_eq!(
+            plausibility.check_position(&mut state, time(12), 40.4, -3.7, receiver),
+            Err(Rejection::Range)
+        );
+
+        // The reference was the outlier, the new track is accepted eventually
+        let mut state = PlausibilityState::default();
+        plausibility.check_position(&mut state, time(0), 52.0, 8.0, None).unwrap();
+        let results: Vec<Result<(), Rejection>> = (1..=6)
+            .map(|seconds| plausibility.check_position(&mut state, time(seconds), 53.6, 10.0, None))
+            .collect();
+        assert_eq!(results.iter().filter(|result| result.is_err()).count(), MAX_REJECTED_IN_ROW);
+        assert_eq!(results.last(), Some(&Ok(())));
+    }
+
+    #[test]
+    fn test_check_altitude() {
+        let plausibility = Plausibility::default();
+        let mut state = PlausibilityState::default();
+        assert_eq!(plausibility.check_altitude(&mut state, time(0), 10000.0), Ok(()));
+        assert_eq!(plausibility.check_altitude(&mut state, time(1), 10300.0), Ok(()));
+        assert_eq!(plausibility.check_altitude(&mut state, time(2), 30000.0), Err(Rejection::VerticalRate));
+        assert_eq!(plausibility.check_altitude(&mut state, time(60), 20000.0), Ok(()));
+
+        let disabled = Plausibility { enabled: false, ..Plausibility::default() };
+        assert_eq!(disabled.check_altitude(&mut state, time(61), 40000.0), Ok(()));
+    }
+}
@@ -0,0 +1,115 @@
+// Bridge from the network tasks to Bevy, systems don't have to lock SharedDataDb every frame.
+//
+// Changes of SharedDataDb are drained once per frame, the state of the changed aircraft is read
+// with a single lock, mirrored in `AircraftStates` and announced as events other plugins can use.
+
+use std::collections::{HashMap, HashSet};
+
+use bevy::prelude::*;
+use tokio::sync::broadcast;
+use tokio::sync::broadcast::error::TryRecvError;
+
+use crate::ShareStruct;
+use crate::data_share::{AircraftChange, AircraftState};
+
+pub fn plugin(app: &mut App) {
+    app.init_resource::<AircraftStates>()
+        .add_event::<AircraftAppeared>()
+        .add_event::<AircraftUpdated>()
+        .add_event::<AircraftLost>()
+        .add_systems(Startup, subscribe_changes)
+        .add_systems(PreUpdate, drain_changes);
+}
+
+// New aircraft, with its first state
+#[derive(Event, Debug, Clone)]
+pub struct AircraftAppeared(pub AircraftState);
+
+// Changed aircraft, with its current state
+#[derive(Event, Debug, Clone)]
+pub struct AircraftUpdated(pub AircraftState);
+
+// Expired aircraft, hex ident
+#[derive(Event, Debug, Clone)]
+pub struct AircraftLost(pub String);
+
+// Current state of all aircraft, updated once per frame
+#[derive(Resource, Default)]
+pub struct AircraftStates {
+    pub aircraft: HashMap<String, AircraftState>, // Hex ident and state
+    pub generation: u64, // See SharedDataDb::get_generation, changes when a snapshot has been restored
+}
+
+#[derive(Resource)]
+struct AircraftChanges {
+    receiver: broadcast::Receiver<AircraftChange>,
+    resync: bool, // Compare all aircraft, e.g. changes got lost
+}
+
+fn subscribe_changes(mut commands: Commands, read: Res<ShareStruct>) {
+    let receiver = read.0.lock().unwrap().subscribe_changes();
+    // Aircraft known before subscribing are picked up by the first resync
+    commands.insert_resource(AircraftChanges { receiver, resync: true });
+}
+
+fn drain_changes(
+    changes: Option<ResMut<AircraftChanges>>,
+    read: Res<ShareStruct>,
+    mut states: ResMut<AircraftStates>,
+    mut appeared: EventWriter<AircraftAppeared>,
+    mut updated: EventWriter<AircraftUpdated>,
+    mut lost: EventWriter<AircraftLost>,
+) {
+    let Some(mut changes) = changes else {
+        return;
+    };
+    let mut changed: HashSet<String> = HashSet::new();
+    loop {
+        match changes.receiver.try_recv() {
+            Ok(change) => {
+                changed.insert(change.hex_ident().to_string());
+            }
+            Err(TryRecvError::Lagged(_)) => changes.resync = true,
+            Err(TryRecvError::Empty) | Err(TryRecvError::Closed) => break,
+        }
+    }
+    if changed.is_empty() && !changes.resync {
+        return;
+    }
+
+    // The only lock of this frame
+    let (current, generation) = {
+        let read_tmp = read.0.lock().unwrap();
+        if changes.resync {
+            changed.extend(read_tmp.get_planes_id().iter().map(|plane_id| plane_id.to_string()));
+            changed.extend(states.aircraft.keys().cloned());
+        }
+        let current: Vec<(String, Option<AircraftState>)> = changed
+            .into_iter()
+            .map(|plane_id| {
+                let state = read_tmp.get_aircraft_state(plane_id.clone());
+                (plane_id, state)
+            })
+            .collect();
+        (current, read_tmp.get_generation())
+    };
+    changes.resync = false;
+    states.generation = generation;
+
+    for (plane_id, state) in current {
+        match state {
+            Some(state) => {
+                if states.aircraft.insert(plane_id, state.clone()).is_some() {
+                    updated.write(AircraftUpdated(state));
+                } else {
+                    appeared.write(AircraftAppeared(state));
+                }
+            }
+            None => {
+                if states.aircraft.remove(&plane_id).is_some() {
+                    lost.write(AircraftLost(plane_id));
+                }
+            }
+        }
+    }
+}
@@ -3,11 +3,17 @@ use std::collections::{HashMap, HashSet};
 
 use bevy::prelude::*;
 use bevy_egui::egui::{Color32, RichText};
+use chrono::{DateTime, Utc};
 use bevy_egui::{EguiContexts, EguiPlugin, EguiPrimaryContextPass, egui};
 
 use crate::plugin_sound::*;
 use crate::squawks::get_transponder_description;
 use crate::data_share::*;
+use crate::math::haversine_distance;
+use crate::plugin_bridge::{AircraftAppeared, AircraftStates, AircraftUpdated};
+use crate::export::{collect_tracks, export_to_file, ExportFormat};
+use crate::replay::REPLAY;
+use crate::source_health::{get_source_health, SourceState};
 
 #[derive(Default, Resource)]
 pub struct UiState {
@@ -25,6 +31,11 @@ pub struct UiState {
     pub max_speed: f32,
     // Checkbox for every active plane
     pub plane_checkbox: HashMap<String, bool>,
+    // Unix time of the replay timeline while dragging
+    pub replay_scrub: Option<i64>,
+    // Track export of the aircraft list, result is the file written or an error
+    pub export_format: ExportFormat,
+    pub export_result: Option<String>,
 }
 
 impl UiState {
@@ -38,18 +49,158 @@ pub fn plugin(app: &mut App) {
     app.init_resource::<UiState>()
         .insert_resource(UiState::default())
         .add_plugins(EguiPlugin::default())
+        .add_systems(Update, update_statistics)
         .add_systems(EguiPrimaryContextPass, ui_system);
 }
 
+// Running statistics from the aircraft states announced by the bridge
+fn update_statistics(
+    mut appeared: EventReader<AircraftAppeared>,
+    mut updated: EventReader<AircraftUpdated>,
+    mut ui_state: ResMut<UiState>,
+) {
+    let appeared = appeared.read().map(|AircraftAppeared(state)| state);
+    let updated = updated.read().map(|AircraftUpdated(state)| state);
+    for state in appeared.chain(updated) {
+        ui_state.plane_ids.insert(state.hex_ident.clone());
+
+        // Height level of positions only
+        if let (Some(_), Some(height_level)) = (state.latitude, state.altitude) {
+            ui_state.max_height_level = ui_state.max_height_level.max(height_level);
+            ui_state.min_height_level = Some(
+                ui_state
+                    .min_height_level
+                    .map_or(height_level, |min| min.min(height_level)),
+            );
+        }
+
+        if let Some(ground_speed) = state.ground_speed {
+            if ground_speed > ui_state.max_speed {
+                ui_state.max_speed = ground_speed;
+            }
+            if ground_speed < ui_state.min_speed.map_or(1000.0, |value| value) {
+                ui_state.min_speed = Some(ground_speed);
+            }
+        }
+
+        if let Some(vertical_rate) = state.vertical_rate {
+            ui_state.min_vertical_rate = ui_state.min_vertical_rate.min(vertical_rate);
+            ui_state.max_vertical_rate = ui_state.max_vertical_rate.max(vertical_rate);
+        }
+
+        if let Some(dist_to_antenna) = distance_to_antenna(state) {
+            ui_state.max_distance_to_antenna = ui_state.max_distance_to_antenna.max(dist_to_antenna);
+        }
+    }
+}
+
+// Distance to antenna
+// Antennenposition 53.5718392,9.9834842
+// TODO: Fix static setup
+fn distance_to_antenna(state: &AircraftState) -> Option<f32> {
+    Some(haversine_distance(53.5718392, 9.9834842, state.latitude?, state.longitude?))
+}
+
+// Status, age and lifecycle events of an aircraft, read from SharedDataDb while hovered
+fn status_details(data_share: &SharedDataDb, state: &AircraftState) -> String {
+    let plane_id = &state.hex_ident;
+    let mut status_str = match state.status {
+        Some(status) => format!("Status: {}", status.description()),
+        None => "Status: -".to_string(),
+    };
+    let age_str = match state.age_state {
+        AgeState::Stale => "stale, no recent position",
+        AgeState::Lost => "lost, no recent messages",
+        _ => "active",
+    };
+    status_str.push_str(&format!(
+        "\nAge: {} ({} s since last message)",
+        age_str,
+        data_share.get_last_seen(plane_id.to_string())
+    ));
+    if let Some((time, rejection)) = data_share.get_last_rejection(plane_id.to_string()) {
+        status_str.push_str(&format!(
+            "\nLast rejected: {} {}",
+            time.format("%H:%M:%S"),
+            rejection.description()
+        ));
+    }
+    for (time, event) in data_share.get_events(plane_id.to_string(), 5).iter() {
+        let event_str = match event {
+            AircraftEvent::Appeared => "appeared".to_string(),
+            AircraftEvent::Selected => "selected".to_string(),
+            AircraftEvent::CallSignChanged(call_sign) => format!("call sign {}", call_sign),
+            AircraftEvent::StatusChanged(status) => status.description().to_string(),
+        };
+        status_str.push_str(&format!("\n{} {}", time.format("%H:%M:%S"), event_str));
+    }
+    status_str
+}
+
+// Sources that heard the aircraft, read from SharedDataDb while hovered
+fn receivers_details(data_share: &SharedDataDb, plane_id: &str) -> String {
+    let receivers_description = data_share
+        .get_receivers(plane_id.to_string())
+        .iter()
+        .map(|(source, reception)| {
+            let signal = reception
+                .signal_level
+                .map(|signal| format!(", {:.1} dBFS", signal))
+                .unwrap_or_default();
+            format!(
+                "{}: {} - {}, {} messages, {} duplicates, {} rejected{}",
+                source,
+                reception.first_heard.format("%H:%M:%S"),
+                reception.last_heard.format("%H:%M:%S"),
+                reception.counters.messages,
+                reception.counters.duplicates,
+                reception.counters.rejected,
+                signal
+            )
+        })
+        .collect::<Vec<String>>()
+        .join("\n");
+    format!(
+        "Last minute: {} messages\n{}",
+        data_share.get_last_minutes(plane_id.to_string(), 1).len(),
+        receivers_description
+    )
+}
+
+// Added aircraft data, e.g. from hexdb.io
+fn added_aircraft_details(plane_id: &str) -> String {
+    let mut added_aircraft_data = "No additional data.".to_string();
+    if let Ok(data_store) = AIRCRAFT_ADD_DATA.lock()
+        && let Some(added_aircraft_data_tmp) = data_store.get(plane_id)
+    {
+        // Map struct data to string
+        added_aircraft_data = format!(
+            "Mode S: {}\nManufacturer: {}\nAircraft type: {}\nICAO type: {}\nOperator Flag Code: {}\nRegistration: {}\nOwners: {}",
+            added_aircraft_data_tmp.mode_s,
+            added_aircraft_data_tmp.manufacturer,
+            added_aircraft_data_tmp.aircraft_type,
+            added_aircraft_data_tmp.icao_type_code,
+            added_aircraft_data_tmp.operator_flag_code,
+            added_aircraft_data_tmp.registration,
+            added_aircraft_data_tmp.registered_owners,
+        );
+    }
+    added_aircraft_data
+}
+
+// The aircraft list is drawn from AircraftStates, SharedDataDb is only locked for sections
+// that are open or hovered, so the network tasks aren't blocked for a whole egui pass
 fn ui_system(
     mut contexts: EguiContexts,
     read: Res<ShareStruct>,
+    states: Res<AircraftStates>,
     mut ui_state: ResMut<UiState>,
     cooldown: Res<SoundCooldown>,
     mut event_writer: EventWriter<PlaySoundEvent>,
 ) {
-    let read_tmp = read.0.lock().unwrap();
-    let plane_list = read_tmp.get_planes_id();
+    // Sorted, the rows would change places otherwise
+    let mut plane_list: Vec<&AircraftState> = states.aircraft.values().collect();
+    plane_list.sort_by(|a, b| a.hex_ident.cmp(&b.hex_ident));
     let number_of_planes = plane_list.len();
 
     egui::Window::new("Luftraum").show(contexts.ctx_mut().expect("egui-show().error"), |ui| {
@@ -96,38 +247,205 @@ fn ui_system(
             ui.label(max_vertical_rate_label);
             ui.label(min_height_level_label);
             ui.label(max_height_level_label);
+
+            // Only locked while the section is open
+            let (dropped_messages, source_counters, heartbeats) = {
+                let read_tmp = read.0.lock().unwrap();
+                (read_tmp.get_dropped_messages(), read_tmp.get_source_counters(), read_tmp.get_heartbeats())
+            };
+
+            // Messages that could not be decoded
+            for (reason, count) in dropped_messages {
+                ui.label(format!("Dropped, {}: {}", reason, count));
+            }
+
+            // Messages per source, duplicates arrived via another source before,
+            // rejected ones had an implausible position or altitude
+            for (source, counters) in source_counters {
+                ui.label(format!(
+                    "Source, {}: {} messages, {} duplicates, {} rejected",
+                    source, counters.messages, counters.duplicates, counters.rejected
+                ));
+            }
+
+            // Heartbeats (CLK records) of SBS sources
+            for (source, seconds) in heartbeats {
+                ui.label(format!("Last CLK, {}: {} s ago", source, seconds));
+            }
         });
 
+        // Replay of a recorded log, pause, resume, speed and timeline
+        if let Ok(mut replay) = REPLAY.lock() && let Some(replay) = replay.as_mut() {
+            ui.collapsing("Replay", |ui| {
+                ui.label(format!("File: {}", replay.file));
+                let position = replay
+                    .position
+                    .map(|position| position.format("%Y-%m-%d %H:%M:%S").to_string())
+                    .unwrap_or("-".to_string());
+                let state = if replay.finished {
+                    "finished"
+                } else if replay.paused {
+                    "paused"
+                } else {
+                    "running"
+                };
+                ui.label(format!("Position: {} ({}, {} messages)", position, state, replay.messages));
+                ui.horizontal(|ui| {
+                    let pause_label = if replay.paused { "Resume" } else { "Pause" };
+                    if ui.button(pause_label).clicked() {
+                        replay.paused = !replay.paused;
+                    }
+                    for (label, speed) in [("1x", 1.0), ("10x", 10.0), ("60x", 60.0), ("Max", 0.0)] {
+                        ui.radio_value(&mut replay.speed, speed, label);
+                    }
+                });
+                if let (Some(start), Some(end)) = (replay.start, replay.end) {
+                    // Seek when the scrub bar is released, the position is kept while dragging
+                    let current = replay
+                        .seek
+                        .or(replay.position)
+                        .map_or(start.timestamp(), |position| position.timestamp());
+                    let mut scrub = ui_state.replay_scrub.unwrap_or(current);
+                    let response = ui.add(
+                        egui::Slider::new(&mut scrub, start.timestamp()..=end.timestamp())
+                            .show_value(false)
+                            .text(format!("{}", DateTime::from_timestamp(scrub, 0).unwrap_or(start).format("%H:%M:%S"))),
+                    );
+                    if response.dragged() {
+                        ui_state.replay_scrub = Some(scrub);
+                    } else if response.drag_stopped() || response.changed() {
+                        ui_state.replay_scrub = None;
+                        replay.seek = DateTime::from_timestamp(scrub, 0);
+                    }
+                }
+            });
+        }
+
+        // Connection health of all data sources
+        let sources = get_source_health();
+        if !sources.is_empty() {
+            ui.collapsing("Sources", |ui| {
+                egui::Grid::new("sources").show(ui, |ui| {
+                    for label in ["Source", "Kind", "State", "Since", "Msg/s", "Messages", "Bytes", "Reconnects", "Last error"] {
+                        ui.label(label);
+                    }
+                    ui.end_row();
+                    for (source, health) in sources.iter() {
+                        let (state, color) = match health.state {
+                            SourceState::Connecting => ("connecting", Color32::YELLOW),
+                            SourceState::Connected => ("connected", Color32::GREEN),
+                            SourceState::Failed => ("failed", Color32::RED),
+                        };
+                        ui.label(source);
+                        ui.label(health.kind);
+                        ui.label(RichText::new(state).color(color));
+                        ui.label(health.since.format("%H:%M:%S").to_string());
+                        ui.label(format!("{:.1}", health.current_rate()));
+                        ui.label(health.messages.to_string());
+                        ui.label(health.bytes.to_string());
+                        ui.label(health.reconnects.to_string());
+                        match &health.last_error {
+                            Some((time, error)) => {
+                                ui.label(time.format("%H:%M:%S").to_string()).on_hover_text(error);
+                            }
+                            None => {
+                                ui.label("-");
+                            }
+                        }
+                        ui.end_row();
+                    }
+                });
+            });
+        }
+
+        // Feeders pushing data via UDP
+        if let Ok(senders) = UDP_SENDERS.lock() && !senders.is_empty() {
+            ui.collapsing("UDP senders", |ui| {
+                egui::Grid::new("udp_senders").show(ui, |ui| {
+                    for label in ["Sender", "Listener", "Datagrams", "Bytes", "Dropped", "Last seen"] {
+                        ui.label(label);
+                    }
+                    ui.end_row();
+                    // Sorted, the rows would change places every frame otherwise
+                    let mut senders: Vec<_> = senders.iter().collect();
+                    senders.sort_by_key(|(sender, _)| **sender);
+                    for (sender, stats) in senders {
+                        ui.label(sender.to_string());
+                        ui.label(&stats.listener);
+                        ui.label(stats.datagrams.to_string());
+                        ui.label(stats.bytes.to_string());
+                        ui.label(stats.dropped.to_string());
+                        ui.label(stats.last_seen.format("%H:%M:%S").to_string());
+                        ui.end_row();
+                    }
+                });
+            });
+        }
+
         // List all planes
         let heading = format!("Aircraft ({number_of_planes})");
         egui::CollapsingHeader::new(heading)
             .default_open(true)
             .show(ui, |ui| {
+                // Export tracks of the selected (checked) or all aircraft
+                ui.horizontal(|ui| {
+                    egui::ComboBox::from_id_salt("export_format")
+                        .selected_text(ui_state.export_format.name())
+                        .show_ui(ui, |ui| {
+                            for format in ExportFormat::ALL {
+                                ui.selectable_value(&mut ui_state.export_format, format, format.name());
+                            }
+                        });
+                    let selected: Vec<String> = ui_state
+                        .plane_checkbox
+                        .iter()
+                        .filter(|(_, selected)| **selected)
+                        .map(|(plane_id, _)| plane_id.clone())
+                        .collect();
+                    let mut export = None;
+                    if ui.add_enabled(!selected.is_empty(), egui::Button::new("Export selected")).clicked() {
+                        let name = if selected.len() == 1 { selected[0].clone() } else { "selected".to_string() };
+                        export = Some((selected, name));
+                    }
+                    if ui.button("Export all").clicked() {
+                        export = Some((vec![], "all".to_string()));
+                    }
+                    if let Some((plane_ids, name)) = export {
+                        let tracks = collect_tracks(&read.0.lock().unwrap(), &plane_ids);
+                        ui_state.export_result = Some(
+                            match export_to_file(&tracks, ui_state.export_format, &format!("luftraum-{}", name)) {
+                                Ok(path) => format!("{} aircraft exported to {}", tracks.len(), path),
+                                Err(e) => format!("Export failed: {}", e),
+                            },
+                        );
+                    }
+                });
+                if let Some(export_result) = &ui_state.export_result {
+                    ui.label(export_result);
+                }
+
                 egui::ScrollArea::vertical().show(ui, |ui| {
                     egui::Grid::new("some_unique_id").show(ui, |ui| {
                         // Headline
                         ui.centered_and_justified(|ui| {
                             ui.label(RichText::new("HEX")); //.strong());
                         });
-                        let labels = ["Squawk", "Altitude", "Vertical", "Speed", "Track", "Call", "Ground", "DTA"];
+                        let labels = ["Squawk", "Altitude", "Vertical", "Speed", "Track", "Call", "Ground", "DTA", "Pos", "Rx"];
                         for label in labels {
                             ui.label(label);
                         }
                         ui.end_row();
 
                         // List of planes
-                        for plane_id in plane_list.clone() {
-                            // Statistics
-                            ui_state.plane_ids.insert(plane_id.to_string());
+                        for state in plane_list.iter() {
+                            let plane_id = state.hex_ident.as_str();
 
                             // Squawk, description and play sound
-                            let squawk;
                             let mut squawk_str = "-".to_string();
                             let mut squawk_description = "-".to_string();
                             let mut color = Color32::GRAY;
-                            if let Some(squawk_tmp) = read_tmp.get_squawk(plane_id.to_string()) {
-                                squawk = squawk_tmp;
-                                if let Some(squawk) = get_transponder_description(squawk) {
+                            if let Some(squawk_tmp) = state.squawk {
+                                if let Some(squawk) = get_transponder_description(squawk_tmp) {
                                     color = squawk.1.to_color32();
                                     squawk_description = squawk.0.to_string();
 
@@ -143,136 +461,99 @@ fn ui_system(
                             }
 
                             // Height level
-                            let height_level = read_tmp
-                                .get_latest_known_altitude(plane_id.to_string())
+                            let height_level = state
+                                .altitude
                                 .map(|alt| alt.to_string())
                                 .unwrap_or("-".to_string());
 
-                            // Update statistics
-                            if let Some(height_level) = read_tmp
-                                .get_latest_known_pos(plane_id.to_string())
-                                .map(|pos| pos.2)
-                            {
-                                ui_state.max_height_level =
-                                    ui_state.max_height_level.max(height_level);
-                                ui_state.min_height_level = Some(
-                                    ui_state
-                                        .min_height_level
-                                        .map_or(height_level, |min| min.min(height_level)),
-                                );
-                            }
-
                             // Speed over ground
-                            let ground_speed = read_tmp
-                                .get_ground_speed(plane_id.to_string())
+                            let ground_speed = state
+                                .ground_speed
                                 .map(|speed| speed.to_string())
                                 .unwrap_or("-".to_string());
 
-                            // Speed over ground statics
-                            if let Some(ground_speed) =
-                                read_tmp.get_ground_speed(plane_id.to_string())
-                            {
-                                if ground_speed > ui_state.max_speed {
-                                    ui_state.max_speed = ground_speed;
-                                }
-                                if ground_speed < ui_state.min_speed.map_or(1000.0, |value| value) {
-                                    ui_state.min_speed = Some(ground_speed);
-                                }
-                            }
-
                             // Track
-                            let track = read_tmp
-                                .get_track(plane_id.to_string())
+                            let track = state
+                                .track
                                 .map(|t| t.to_string())
                                 .unwrap_or("-".to_string());
 
+                            // Filtered by the motion model
+                            let motion_value = |value: Option<Option<f32>>, unit: &str| {
+                                value
+                                    .flatten()
+                                    .map(|value| format!("Filtered: {:.0} {}", value, unit))
+                                    .unwrap_or("Filtered: -".to_string())
+                            };
+                            let ground_speed_filtered = motion_value(state.motion.map(|motion| motion.ground_speed), "kt");
+                            let track_filtered = motion_value(state.motion.map(|motion| motion.track), "°");
+
                             // Call sign
-                            let call_sign = read_tmp.get_call_sign(plane_id.to_string())
+                            let call_sign = state.call_sign.clone()
                                 .filter(|s| !s.is_empty()).unwrap_or("-".to_string());
 
-                            // Added aircraft data
-                            let mut added_aircraft_data = "No additional data.".to_string();
-                            let data_store = AIRCRAFT_ADD_DATA.lock();
-
-                            if let Ok(data_store) = data_store {
-                                let added_aircraft_data_struct =
-                                    data_store.get(&plane_id.to_string());
-
-                                // Map struct data to string
-                                if let Some(added_aircraft_data_tmp) = added_aircraft_data_struct {
-                                    added_aircraft_data = format!(
-                                        "Mode S: {}\nManufacturer: {}\nAircraft type: {}\nICAO type: {}\nOperator Flag Code: {}\nRegistration: {}\nOwners: {}",
-                                        added_aircraft_data_tmp.mode_s,
-                                        added_aircraft_data_tmp.manufacturer,
-                                        added_aircraft_data_tmp.aircraft_type,
-                                        added_aircraft_data_tmp.icao_type_code,
-                                        added_aircraft_data_tmp.operator_flag_code,
-                                        added_aircraft_data_tmp.registration,
-                                        added_aircraft_data_tmp.registered_owners,
-                                    );
-
-                                }
-                            }
-
                             // Is on ground
-                            let on_ground_str = read_tmp
-                                .is_on_ground(plane_id.to_string())
+                            let on_ground_str = state
+                                .is_on_ground
                                 .filter(|&is_on_ground| is_on_ground)
                                 .map(|_| "on ground".to_string())
                                 .unwrap_or("-".to_string());
 
                             // Vertical rate
-                            let vertical_rate = read_tmp.get_vertical_rate(plane_id.to_string());
-
-                            // Update statistics
-                            if vertical_rate.is_some() {
-                                ui_state.min_vertical_rate =
-                                    ui_state.min_vertical_rate.min(vertical_rate.unwrap());
-                                ui_state.max_vertical_rate =
-                                    ui_state.max_vertical_rate.max(vertical_rate.unwrap());
-                            }
-
-                            let vertical_rate_str = vertical_rate
+                            let vertical_rate_str = state
+                                .vertical_rate
                                 .map(|t| t.to_string())
                                 .unwrap_or("-".to_string());
 
-                            // Distance to antenna
-                            // Antennenposition 53.5718392,9.9834842
-                            // TODO: Fix static setup
-                            let dist_to_antenna = read_tmp
-                                .get_plane_distance_to_lat_lon(
-                                    plane_id.to_string(),
-                                    53.5718392,
-                                    9.9834842,
-                                )
-                                .unwrap_or(0.0);
-                            let dist_to_antenna_str = if dist_to_antenna == 0.0 {
-                                "-".to_owned()
-                            } else {
-                                format!("{:05.1}", dist_to_antenna)
+                            let dist_to_antenna_str = distance_to_antenna(state)
+                                .map(|dist_to_antenna| format!("{:05.1}", dist_to_antenna))
+                                .unwrap_or("-".to_owned());
+
+                            // Position source, ADS-B or multilateration
+                            let pos_source_str = match state.position_source {
+                                Some(PositionSource::Adsb) => "ADS-B",
+                                Some(PositionSource::Mlat) => "MLAT",
+                                None => "-",
                             };
 
-                            // Update statistics
-                            if dist_to_antenna > ui_state.max_distance_to_antenna {
-                                ui_state.max_distance_to_antenna = dist_to_antenna.clone();
-                            }
+                            // Stale and lost aircraft are greyed out, like the ghosted planes in the 3D view
+                            let hex_color = match state.age_state {
+                                AgeState::Stale | AgeState::Lost => Color32::DARK_GRAY,
+                                _ => Color32::LIGHT_GRAY,
+                            };
 
                             let checkbox_value = ui_state.selected(plane_id);
 
-                            // Build row
+                            // Build row, details are read when hovered
                             ui.checkbox(
                                 checkbox_value,
-                                RichText::new(plane_id.to_string()).color(Color32::LIGHT_GRAY),
-                            );
+                                RichText::new(plane_id).color(hex_color),
+                            ).on_hover_ui(|ui| {
+                                ui.label(status_details(&read.0.lock().unwrap(), state));
+                            });
                             ui.label(RichText::new(squawk_str).color(color)).on_hover_text(squawk_description);
-                            ui.label(height_level);
+                            ui.label(height_level).on_hover_ui(|ui| {
+                                // Trend from the history
+                                let height_level_trend = read.0.lock().unwrap()
+                                    .get_sample_at(plane_id.to_string(), Utc::now() - chrono::Duration::minutes(5))
+                                    .and_then(|sample| sample.altitude)
+                                    .map(|alt| format!("5 min ago: {} ft", alt))
+                                    .unwrap_or("5 min ago: -".to_string());
+                                ui.label(height_level_trend);
+                            });
                             ui.label(vertical_rate_str);
                             //ui.label(vertical_rate_simple_str);
-                            ui.label(ground_speed);
-                            ui.label(track);
-                            ui.label(call_sign).on_hover_text(added_aircraft_data);
+                            ui.label(ground_speed).on_hover_text(ground_speed_filtered);
+                            ui.label(track).on_hover_text(track_filtered);
+                            ui.label(call_sign).on_hover_ui(|ui| {
+                                ui.label(added_aircraft_details(plane_id));
+                            });
                             ui.label(on_ground_str);
                             ui.label(dist_to_antenna_str);
+                            ui.label(pos_source_str);
+                            ui.label(state.receivers.to_string()).on_hover_ui(|ui| {
+                                ui.label(receivers_details(&read.0.lock().unwrap(), plane_id));
+                            });
                             ui.end_row();
                         }
                     });
@@ -1,41 +1,35 @@
-use std::time::Duration;
-
 use bevy::color::palettes::tailwind::{BLUE_500, RED_400, YELLOW_200, YELLOW_500};
 use bevy::prelude::*;
 use bevy::render::mesh::{Indices, PrimitiveTopology}; // Plane track mesh
 use bevy::render::render_asset::RenderAssetUsages;
 
-use crate::ShareStruct;
+use crate::data_share::{AgeState, AircraftState, PositionSource};
 use crate::math::*;
+use crate::plugin_bridge::{AircraftLost, AircraftStates};
 use crate::plugin_egui::*;
 
 pub fn plugin(app: &mut App) {
     //app.add_systems(Startup, spawn_plane)
-    app.insert_resource(TimerResource(Timer::new(
-        Duration::from_secs(10),
-        TimerMode::Repeating,
-    )))
-    .add_systems(
+    app.add_systems(
         Update,
         (
+            rebuild_planes,
             create_planes,
             update_planes,
             update_route,
-            increase_plane_last_seen,
             despawn_planes,
+            ghost_planes,
             show_tracks,
         ),
     );
 }
 
-#[derive(Resource)]
-struct TimerResource(Timer);
-
 #[derive(Component, Resource)]
 pub struct Plane {
     pub hex: String,              // Plane hex-id
     pub pos: Vec<[f32; 3]>,       // Collects all [lat, lon, alt] to show flight path in Bevy coordinates
     pub track_id: Option<Entity>, // Bevy entity id of track
+    pub age_state: AgeState,      // Age state the plane is rendered with
 }
 
 impl Plane {
@@ -44,6 +38,7 @@ impl Plane {
             hex,
             pos: Vec::new(),
             track_id: None,
+            age_state: AgeState::Active,
         }
     }
 }
@@ -55,48 +50,59 @@ struct ChildEntities {
     second_child: Entity,
 }
 
-// Create update all planes positions
-pub fn update_planes(mut query: Query<(&mut Transform, &mut Plane)>, read: ResMut<ShareStruct>) {
-    // TODO: Beautify code
-    let read_tmp = read.0.lock().unwrap();
-    let plane_list: Vec<String> = read_tmp
-        .get_planes_id()
-        .iter()
-        .map(|&s| s.to_string())
-        .collect();
-
-    // Get all existing plane_id in database
-    for plane_id in plane_list {
-        'inner: for mut plane in query.iter_mut() {
-            if plane_id == plane.1.hex {
-                // Update position if all Some has data
-                let pos = read_tmp.get_latest_known_pos(plane_id.to_string());
-
-                if pos.is_some() {
-                    let lat = pos.unwrap().0;
-                    let lon = pos.unwrap().1;
-                    let lat1 = map_range(lat, 50.0, 55.0, 1000.0, -1000.0);
-                    let lon1 = map_range(lon, 5.0, 10.0, -1000.0, 1000.0);
-                    let height = pos.unwrap().2;
-
-                    // TODO: Distribute scale factor and clarify magic 0.3048
-                    let scale = 0.00361;
-                    plane.0.translation = Vec3::new(lon1, height * scale * 0.3048, lat1); // What was 0.3048 again?
+// Planes are moved towards the motion model every frame, further jumps are taken at once, e.g. after spawning
+const SNAP_DISTANCE: f32 = 50.0;
+const SMOOTHING: f32 = 10.0; // Share of the distance per second
+
+// Position in Bevy coordinates
+fn bevy_coordinates(lat: f32, lon: f32, height: f32) -> Vec3 {
+    let lat1 = map_range(lat, 50.0, 55.0, 1000.0, -1000.0);
+    let lon1 = map_range(lon, 5.0, 10.0, -1000.0, 1000.0);
+    // TODO: Distribute scale factor and clarify magic 0.3048
+    let scale = 0.00361;
+    Vec3::new(lon1, height * scale * 0.3048, lat1) // What was 0.3048 again?
+}
 
-                    // Save position, to show flight path
-                    plane.1.pos.push([lon1, height * scale * 0.3048, lat1]);
-                }
+// Reported position in Bevy coordinates, None without position or altitude
+fn bevy_position(state: &AircraftState) -> Option<Vec3> {
+    Some(bevy_coordinates(state.latitude?, state.longitude?, state.altitude?))
+}
 
-                // Rotate plane
-                if let Some(track) = read_tmp.get_track(plane_id.to_string()) {
-                    // Real degree to bevy degree
-                    let new_track: f32 = (180.0 - track + 360.0) % 360.0;
-                    plane.0.rotation = Quat::from_rotation_y(new_track.to_radians());
-                }
+// Move planes smoothly along the motion model, the flight path keeps the reported positions
+pub fn update_planes(mut query: Query<(&mut Transform, &mut Plane)>, states: Res<AircraftStates>, time: Res<Time>) {
+    for (mut transform, mut plane) in query.iter_mut() {
+        let Some(state) = states.aircraft.get(&plane.hex) else {
+            continue;
+        };
+
+        // Save position, to show flight path
+        if let Some(position) = bevy_position(state)
+            && plane.pos.last() != Some(&position.to_array())
+        {
+            plane.pos.push(position.to_array());
+        }
 
-                break 'inner;
+        // Filtered and extrapolated to this frame, reported position without motion model
+        let motion = state.motion.map(|motion| motion.now());
+        let target = motion
+            .and_then(|motion| Some(bevy_coordinates(motion.latitude, motion.longitude, motion.altitude?)))
+            .or_else(|| bevy_position(state));
+        if let Some(target) = target {
+            if transform.translation.distance(target) > SNAP_DISTANCE {
+                transform.translation = target;
+            } else {
+                let share = (SMOOTHING * time.delta_secs()).min(1.0);
+                transform.translation = transform.translation.lerp(target, share);
             }
         }
+
+        // Rotate plane by heading and climb angle
+        if let Some(track) = motion.and_then(|motion| motion.track).or(state.track) {
+            // Real degree to bevy degree
+            let new_track: f32 = (180.0 - track + 360.0) % 360.0;
+            let climb_angle = motion.map(|motion| motion.climb_angle()).unwrap_or(0.0);
+            transform.rotation = Quat::from_rotation_y(new_track.to_radians()) * Quat::from_rotation_x(-climb_angle);
+        }
     }
 }
 
@@ -105,18 +111,16 @@ pub fn create_planes(
     mut meshes: ResMut<Assets<Mesh>>,
     mut materials: ResMut<Assets<StandardMaterial>>,
     query: Query<(&mut Transform, &Plane)>,
-    read: Res<ShareStruct>,
+    states: Res<AircraftStates>,
     asset_server: Res<AssetServer>,
 ) {
-    let read_tmp = read.0.lock().unwrap();
-    let plane_list = read_tmp.get_planes_id();
     let spawned_list = query
         .iter()
         .map(|e| e.1.hex.clone())
         .collect::<Vec<String>>();
 
-    for plane_id in plane_list {
-        let plane_id_tmp = String::from(plane_id);
+    for plane_id in states.aircraft.keys() {
+        let plane_id_tmp = plane_id.clone();
         // Check is plane already exists
         if !spawned_list.contains(&plane_id_tmp) {
             // Beim Spawnen
@@ -155,21 +159,27 @@ pub fn create_planes(
     }
 }
 
-pub fn update_route(read: Res<ShareStruct>, mut gizmos: Gizmos, ui_state: Res<UiState>) {
-    let read_tmp = read.0.lock().unwrap();
-    let list = read_tmp.get_planes_id();
-
+pub fn update_route(states: Res<AircraftStates>, mut gizmos: Gizmos, ui_state: Res<UiState>) {
     // TODO: Distribute map ranges
-    for plane in list {
-        for plane_data in read_tmp.get_latest_known_pos(plane.to_string()) {
+    for (plane, state) in states.aircraft.iter() {
+        let position = match (state.latitude, state.longitude, state.altitude, state.position_source) {
+            (Some(lat), Some(lon), Some(alt), Some(source)) => Some((lat, lon, alt, source)),
+            _ => None,
+        };
+        if let Some(plane_data) = position {
             let lat1 = map_range(plane_data.0, 50.0, 55.0, 1000.0, -1000.0);
             let lon1 = map_range(plane_data.1, 5.0, 10.0, -1000.0, 1000.0);
             // TODO: Distribute scale factor
             let scale = 0.00361;
+            // Positions from multilateration are marked in a different colour
+            let color = match plane_data.3 {
+                PositionSource::Adsb => RED_400,
+                PositionSource::Mlat => BLUE_500,
+            };
             gizmos.cross(
                 Vec3::new(lon1, plane_data.2 * scale * 0.3048, lat1),
                 5.0,
-                RED_400,
+                color,
             );
 
             // Indicate ground location
@@ -204,45 +214,85 @@ pub fn update_route(read: Res<ShareStruct>, mut gizmos: Gizmos, ui_state: Res<Ui
     }
 }
 
-fn increase_plane_last_seen(
-    time: Res<Time>,
-    mut timer: ResMut<TimerResource>,
-    read: Res<ShareStruct>,
+// Despawn all planes after the shared data has been restored, e.g. by seeking in a replay,
+// create_planes spawns them again with the restored data
+fn rebuild_planes(
+    mut commands: Commands,
+    mut generation: Local<u64>,
+    query: Query<(Entity, &Plane)>,
+    states: Res<AircraftStates>,
 ) {
-    timer.0.tick(time.delta());
-    if timer.0.just_finished() {
-        let mut read_tmp = read.0.lock().unwrap();
-        read_tmp.increase_last_seen(10); // Increase by 10 sec
+    if states.generation == *generation {
+        return;
+    }
+    *generation = states.generation;
+    for (entity, plane) in query.iter() {
+        commands.entity(entity).despawn();
+        if let Some(track) = plane.track_id {
+            commands.entity(track).despawn();
+        }
     }
 }
 
+// Despawn planes expired in the shared data, see data_share::expire_planes
 fn despawn_planes(
     mut commands: Commands,
-    time: Res<Time>,
-    mut timer: ResMut<TimerResource>,
-    mut query: Query<(Entity, &Plane)>,
-    read: Res<ShareStruct>,
+    mut events: EventReader<AircraftLost>,
+    query: Query<(Entity, &Plane)>,
     mut ui_state: ResMut<UiState>,
 ) {
-    timer.0.tick(time.delta());
-    if timer.0.just_finished() {
-        let mut read_tmp = read.0.lock().unwrap();
-        for plane_id in query.iter_mut() {
-            // Plane 'lifetime' if unseen
-            // TODO: Setup time in egui
-            if read_tmp.get_last_seen(plane_id.1.hex.clone()) >= 60 {
-                // Remove Bevy entity
-                commands.entity(plane_id.0).despawn();
-                // Remove from Egui ui state
-                ui_state.plane_checkbox.remove(&plane_id.1.hex.clone());
-                // Remove shared data
-                read_tmp.remove_plane(plane_id.1.hex.clone());
-                // Remove track
-                plane_id
-                    .1
-                    .track_id
-                    .map(|track| commands.entity(track).despawn());
+    for AircraftLost(plane_id) in events.read() {
+        for (entity, plane) in query.iter().filter(|(_, plane)| plane.hex == *plane_id) {
+            // Remove Bevy entity
+            commands.entity(entity).despawn();
+            // Remove track
+            if let Some(track) = plane.track_id {
+                commands.entity(track).despawn();
+            }
+        }
+        // Remove from Egui ui state
+        ui_state.plane_checkbox.remove(plane_id);
+    }
+}
+
+// Render stale and lost planes ghosted, see data_share::Expiry
+fn ghost_planes(
+    mut query: Query<(Entity, &mut Plane)>,
+    children: Query<&Children>,
+    mut mesh_materials: Query<&mut MeshMaterial3d<StandardMaterial>>,
+    mut materials: ResMut<Assets<StandardMaterial>>,
+    states: Res<AircraftStates>,
+) {
+    for (entity, mut plane) in query.iter_mut() {
+        let Some(state) = states.aircraft.get(&plane.hex) else {
+            continue;
+        };
+        if state.age_state == AgeState::Active && plane.age_state == AgeState::Active {
+            continue;
+        }
+        plane.age_state = state.age_state;
+        let alpha = match state.age_state {
+            AgeState::Active => 1.0,
+            AgeState::Stale => 0.5,
+            AgeState::Lost => 0.2,
+        };
+
+        // Materials of the model are shared by all planes, ghosted planes get their own copies.
+        // Checked every frame while ghosted, the model is loaded after the plane has been spawned
+        for child in children.iter_descendants(entity) {
+            let Ok(mut mesh_material) = mesh_materials.get_mut(child) else {
+                continue;
+            };
+            let Some(material) = materials.get(&mesh_material.0) else {
+                continue;
+            };
+            if material.base_color.alpha() == alpha {
+                continue;
             }
+            let mut ghost = material.clone();
+            ghost.base_color.set_alpha(alpha);
+            ghost.alpha_mode = if alpha < 1.0 { AlphaMode::Blend } else { AlphaMode::Opaque };
+            mesh_material.0 = materials.add(ghost);
         }
     }
 }
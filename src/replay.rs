@@ -0,0 +1,490 @@
+// Replay of raw_messages.log, feeds recorded messages into SharedDataDb with the original timing.
+//
+// Every log line is "<unix time>,<source>,<message>", older logs have whole seconds. Sources are
+// "sbs", "beast" and "avr" of the TCP feeds, anything else is an MQTT topic or UDP sender whose
+// payload format is guessed. Replayed messages count for the source "<label>:<logged source>".
+//
+// Seeking restores the latest snapshot before the target, snapshots are taken every
+// SNAPSHOT_INTERVAL_MS of recording time, and replays as fast as possible from there. Beyond
+// MAX_SNAPSHOTS every second snapshot is dropped and the interval doubles.
+
+use chrono::{DateTime, Utc};
+use once_cell::sync::Lazy;
+use serde::Deserialize;
+use std::borrow::Cow;
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::io::SeekFrom;
+use tokio::io::{AsyncBufReadExt, AsyncSeekExt, BufReader};
+use tokio::time::{Duration, sleep};
+
+use crate::beast::BeastFrame;
+use crate::data_share::*;
+use crate::decode::{
+    decode_message_avr, decode_message_beast, decode_message_sbs, ModeSDecoders, PayloadDecoder,
+    PayloadFormat,
+};
+use crate::logging::JsonLogRecord;
+use crate::network::Receiver;
+use crate::source_health::SourceMonitor;
+
+// Pause, speed and seek requests are checked at least this often
+const REPLAY_CHECK_INTERVAL: Duration = Duration::from_millis(100);
+// Milliseconds of recording time between two snapshots of SharedDataDb
+const SNAPSHOT_INTERVAL_MS: u64 = 60_000;
+// Snapshots kept in memory, each holds the complete history of all aircraft
+const MAX_SNAPSHOTS: usize = 32;
+
+#[derive(Debug, Deserialize, Clone)]
+pub struct Replay {
+    #[serde(default = "default_replay_label")]
+    pub label: String,
+    #[serde(default = "default_replay_file")]
+    pub file: String,
+    #[serde(default = "default_replay_speed")]
+    pub speed: f32, // 1.0 = original timing, 10.0 = ten times faster, 0.0 = as fast as possible
+    #[serde(default)]
+    pub paused: bool, // Start paused, resume in the "Replay" section
+}
+
+fn default_replay_label() -> String {
+    "replay".to_string()
+}
+
+fn default_replay_file() -> String {
+    "raw_messages.log".to_string()
+}
+
+fn default_replay_speed() -> f32 {
+    1.0
+}
+
+// State of the running replay, changed by egui
+#[derive(Debug, Clone)]
+pub struct ReplayState {
+    pub file: String,
+    pub speed: f32,
+    pub paused: bool,
+    pub start: Option<DateTime<Utc>>,    // Recording time of the first message
+    pub end: Option<DateTime<Utc>>,      // Recording time of the last message
+    pub position: Option<DateTime<Utc>>, // Recording time of the last replayed message
+    pub seek: Option<DateTime<Utc>>,     // Requested by egui, cleared by the replay task
+    pub messages: usize,
+    pub finished: bool,
+}
+
+fn seek_requested() -> bool {
+    REPLAY
+        .lock()
+        .map(|state| state.as_ref().is_some_and(|state| state.seek.is_some()))
+        .unwrap_or(false)
+}
+
+fn take_seek_request() -> Option<u64> {
+    let mut state = REPLAY.lock().ok()?;
+    let state = state.as_mut()?;
+    state.finished = false;
+    state.seek.take().map(|seek| seek.timestamp_millis().max(0) as u64)
+}
+
+fn update_state(update: impl FnOnce(&mut ReplayState)) {
+    if let Ok(mut state) = REPLAY.lock()
+        && let Some(state) = state.as_mut()
+    {
+        update(state);
+    }
+}
+
+// State of SharedDataDb before the log entry at `offset`
+struct Snapshot {
+    timestamp: u64, // Milliseconds
+    offset: u64,
+    plane_db: PlaneDbSnapshot,
+}
+
+/// Drop every second snapshot, the first one is kept, returns the doubled interval
+fn thin_snapshots(snapshots: &mut Vec<Snapshot>, interval: u64) -> u64 {
+    let mut index = 0;
+    snapshots.retain(|_| {
+        index += 1;
+        index % 2 == 1
+    });
+    interval * 2
+}
+
+pub static REPLAY: Lazy<Mutex<Option<ReplayState>>> = Lazy::new(|| Mutex::new(None));
+
+// One logged message, payloads with newlines continue on the following lines
+struct LogEntry {
+    timestamp: u64, // Unix time in milliseconds
+    source: String,
+    message: String,
+}
+
+/// Unix time in milliseconds of "<seconds>" or "<seconds>.<milliseconds>"
+fn parse_timestamp_ms(field: &str) -> Option<u64> {
+    let (seconds, fraction) = field.split_once('.').unwrap_or((field, ""));
+    if fraction.len() > 3 || !fraction.bytes().all(|byte| byte.is_ascii_digit()) {
+        return None;
+    }
+    let seconds = seconds.parse::<u64>().ok()?;
+    let millis = format!("{:0<3}", fraction).parse::<u64>().ok()?;
+    Some(seconds * 1000 + millis)
+}
+
+/// Split a log line into unix time in milliseconds, source and message, `None` for continuation lines
+///
+/// Lines of the json raw log format are recognized as well.
+pub fn parse_log_line(line: &str) -> Option<(u64, Cow<'_, str>, Cow<'_, str>)> {
+    if line.starts_with('{') {
+        let record: JsonLogRecord = serde_json::from_str(line.trim_end()).ok()?;
+        let timestamp = (record.time.is_finite() && record.time >= 0.0).then(|| (record.time * 1000.0).round() as u64)?;
+        return Some((timestamp, record.source, record.message));
+    }
+    let (timestamp, rest) = line.split_once(',')?;
+    let timestamp = parse_timestamp_ms(timestamp)?;
+    let (source, message) = rest.split_once(',')?;
+    if source.is_empty() {
+        return None;
+    }
+    Some((timestamp, source.into(), message.into()))
+}
+
+/// Replay a recorded log, speed, pause and seek requests are taken from `REPLAY`
+///
+/// The task keeps running at the end of the log, so it is still possible to seek back.
+pub async fn replay_log(
+    data_share: &Arc<Mutex<SharedDataDb>>, replay: Replay, receiver: Option<Receiver>,
+) -> Result<(), Box<dyn std::error::Error>> {
+
+    let monitor = SourceMonitor::new(&replay.label, "replay");
+    let file = match tokio::fs::File::open(&replay.file).await {
+        Ok(file) => file,
+        Err(e) => {
+            monitor.failed(&format!("Opening {}, error: {}", replay.file, e));
+            return Err(e.into());
+        }
+    };
+    monitor.connected();
+    println!("Replaying {}", replay.file);
+
+    // First pass for the time range of the timeline
+    let mut reader = BufReader::new(file);
+    let (start, end) = time_range(&mut reader).await?;
+    reader.seek(SeekFrom::Start(0)).await?;
+
+    if let Ok(mut state) = REPLAY.lock() {
+        *state = Some(ReplayState {
+            file: replay.file.clone(),
+            speed: replay.speed,
+            paused: replay.paused,
+            start: start.and_then(|start| DateTime::from_timestamp_millis(start as i64)),
+            end: end.and_then(|end| DateTime::from_timestamp_millis(end as i64)),
+            position: None,
+            seek: None,
+            messages: 0,
+            finished: false,
+        });
+    }
+
+    let mut replayer = Replayer {
+        label: replay.label.clone(),
+        reference: receiver.as_ref().map(|r| r.position()),
+        mode_s_decoders: HashMap::new(),
+        payload_decoders: HashMap::new(),
+        last_timestamp: None,
+        fast_forward_until: None,
+    };
+
+    let mut snapshots: Vec<Snapshot> = Vec::new();
+    let mut snapshot_interval = SNAPSHOT_INTERVAL_MS;
+    let mut offset: u64 = 0; // Byte offset of the next line
+    let mut pending: Option<(u64, LogEntry)> = None; // Entry and its offset, may continue on the next line
+    let mut line = String::new();
+
+    loop {
+        if let Some(target) = take_seek_request() {
+            // Latest snapshot before the target, or the start of the log
+            let current = replayer.last_timestamp.unwrap_or(0);
+            let snapshot = snapshots.iter().rev().find(|snapshot| snapshot.timestamp <= target);
+            let restore = target < current || snapshot.is_some_and(|snapshot| snapshot.timestamp > current);
+            if restore {
+                let (timestamp, snapshot_offset, plane_db) = match snapshot {
+                    Some(snapshot) => (Some(snapshot.timestamp), snapshot.offset, snapshot.plane_db.clone()),
+                    None => (None, 0, PlaneDbSnapshot::default()),
+                };
+                data_share.lock().unwrap().restore(&plane_db);
+                reader.seek(SeekFrom::Start(snapshot_offset)).await?;
+                offset = snapshot_offset;
+                pending = None;
+                replayer.reset(timestamp);
+            }
+            replayer.fast_forward_until = Some(target);
+            continue;
+        }
+
+        line.clear();
+        let len = reader.read_line(&mut line).await? as u64;
+        if len == 0 {
+            // End of the log, wait for a seek back
+            if let Some((_, entry)) = pending.take() {
+                monitor.received(1, entry.message.len());
+                replayer.replay(data_share, entry).await;
+            }
+            update_state(|state| state.finished = true);
+            sleep(REPLAY_CHECK_INTERVAL).await;
+            continue;
+        }
+        let line_offset = offset;
+        offset += len;
+        let text = line.trim_end_matches(['\r', '\n']);
+
+        match parse_log_line(text) {
+            Some((timestamp, source, message)) => {
+                let entry = LogEntry {
+                    timestamp,
+                    source: source.into_owned(),
+                    message: message.into_owned(),
+                };
+                if let Some((_, entry)) = pending.replace((line_offset, entry)) {
+                    monitor.received(1, entry.message.len());
+                    replayer.replay(data_share, entry).await;
+                }
+
+                // Snapshot of the state before this entry, after a seek back the old ones are reused
+                let last = snapshots.last().map(|snapshot| (snapshot.timestamp, snapshot.offset));
+                let due = last.is_none_or(|(last_timestamp, last_offset)| {
+                    line_offset > last_offset && timestamp >= last_timestamp + snapshot_interval
+                });
+                if due && !seek_requested() {
+                    snapshots.push(Snapshot {
+                        timestamp,
+                        offset: line_offset,
+                        plane_db: data_share.lock().unwrap().snapshot(),
+                    });
+                    if snapshots.len() > MAX_SNAPSHOTS {
+                        snapshot_interval = thin_snapshots(&mut snapshots, snapshot_interval);
+                    }
+                }
+            }
+            None => {
+                if let Some((_, entry)) = pending.as_mut() {
+                    entry.message.push('\n');
+                    entry.message.push_str(text);
+                }
+            }
+        }
+    }
+}
+
+/// Replay a whole log as fast as possible and return at its end, e.g. for exports
+pub async fn load_log(
+    data_share: &Arc<Mutex<SharedDataDb>>, file: &str, receiver: Option<Receiver>,
+) -> Result<(), std::io::Error> {
+
+    let mut lines = BufReader::new(tokio::fs::File::open(file).await?).lines();
+    let mut replayer = Replayer {
+        label: default_replay_label(),
+        reference: receiver.as_ref().map(|r| r.position()),
+        mode_s_decoders: HashMap::new(),
+        payload_decoders: HashMap::new(),
+        last_timestamp: None,
+        fast_forward_until: Some(u64::MAX),
+    };
+
+    let mut pending: Option<LogEntry> = None;
+    while let Some(line) = lines.next_line().await? {
+        match parse_log_line(&line) {
+            Some((timestamp, source, message)) => {
+                let entry = LogEntry {
+                    timestamp,
+                    source: source.into_owned(),
+                    message: message.into_owned(),
+                };
+                if let Some(entry) = pending.replace(entry) {
+                    replayer.replay(data_share, entry).await;
+                }
+            }
+            None => {
+                if let Some(entry) = pending.as_mut() {
+                    entry.message.push('\n');
+                    entry.message.push_str(&line);
+                }
+            }
+        }
+    }
+    if let Some(entry) = pending {
+        replayer.replay(data_share, entry).await;
+    }
+    Ok(())
+}
+
+/// First and last timestamp of a log in milliseconds
+async fn time_range(
+    reader: &mut BufReader<tokio::fs::File>,
+) -> Result<(Option<u64>, Option<u64>), std::io::Error> {
+    let mut start = None;
+    let mut end = None;
+    let mut line = String::new();
+    while reader.read_line(&mut line).await? > 0 {
+        if let Some((timestamp, _, _)) = parse_log_line(&line) {
+            start = start.or(Some(timestamp));
+            end = Some(timestamp);
+        }
+        line.clear();
+    }
+    Ok((start, end))
+}
+
+// Decoders of every recorded source, binary formats keep state between messages
+struct Replayer {
+    label: String,
+    reference: Option<(f64, f64)>,
+    mode_s_decoders: HashMap<String, ModeSDecoders>,
+    payload_decoders: HashMap<String, PayloadDecoder>,
+    last_timestamp: Option<u64>,
+    fast_forward_until: Option<u64>, // Replay as fast as possible till this time, after a seek
+}
+
+impl Replayer {
+    /// Start over at a restored snapshot, decoders forget their state
+    fn reset(&mut self, timestamp: Option<u64>) {
+        self.mode_s_decoders.clear();
+        self.payload_decoders.clear();
+        self.last_timestamp = timestamp;
+    }
+
+    async fn replay(&mut self, data_share: &Arc<Mutex<SharedDataDb>>, entry: LogEntry) {
+        self.wait(entry.timestamp).await;
+        // Skipped, the replay continues somewhere else
+        if seek_requested() {
+            return;
+        }
+
+        let reference = self.reference;
+        let label = format!("{}:{}", self.label, entry.source);
+        // Stored with the recording time
+        let received = DateTime::from_timestamp_millis(entry.timestamp as i64);
+        match entry.source.as_str() {
+            "sbs" => {
+                let _ = decode_message_sbs(data_share, &label, entry.message, received);
+            }
+            "beast" => match BeastFrame::from_log(&entry.message) {
+                Some(frame) => {
+                    let decoder = self
+                        .mode_s_decoders
+                        .entry(entry.source)
+                        .or_insert_with(|| ModeSDecoders::new(reference));
+                    decoder.set_received(received);
+                    decode_message_beast(data_share, &label, decoder, &frame);
+                }
+                None => data_share.lock().unwrap().count_dropped("replay: invalid beast line"),
+            },
+            "avr" => {
+                let decoder = self
+                    .mode_s_decoders
+                    .entry(entry.source)
+                    .or_insert_with(|| ModeSDecoders::new(reference));
+                decoder.set_received(received);
+                decode_message_avr(data_share, &label, decoder, &entry.message);
+            }
+            // MQTT topic or UDP sender, the format is guessed from the first payload
+            _ => {
+                let decoder = self.payload_decoders.entry(entry.source).or_insert_with(|| {
+                    PayloadDecoder::new(&label, PayloadFormat::sniff(&entry.message), reference)
+                });
+                decoder.set_received(received);
+                decoder.decode(data_share, entry.message.as_bytes());
+            }
+        }
+
+        update_state(|state| {
+            state.position = DateTime::from_timestamp_millis(entry.timestamp as i64);
+            state.messages += 1;
+        });
+    }
+
+    /// Wait as long as the recording did, scaled by the speed, and while paused
+    async fn wait(&mut self, timestamp: u64) {
+        let mut remaining = self
+            .last_timestamp
+            .map_or(0.0, |last| timestamp.saturating_sub(last) as f32 / 1000.0);
+        self.last_timestamp = Some(timestamp);
+
+        // Seeking, no waiting till the target is reached
+        if let Some(target) = self.fast_forward_until {
+            if timestamp < target {
+                tokio::task::yield_now().await;
+                return;
+            }
+            self.fast_forward_until = None;
+            remaining = 0.0;
+        }
+
+        loop {
+            if seek_requested() {
+                return;
+            }
+            let (speed, paused) = match REPLAY.lock() {
+                Ok(state) => state
+                    .as_ref()
+                    .map_or((0.0, false), |state| (state.speed, state.paused)),
+                Err(_) => (0.0, false),
+            };
+            if paused {
+                sleep(REPLAY_CHECK_INTERVAL).await;
+                continue;
+            }
+            if remaining <= 0.0 || speed <= 0.0 {
+                break;
+            }
+            // Recorded seconds covered by one check interval
+            let step = remaining.min(REPLAY_CHECK_INTERVAL.as_secs_f32() * speed);
+            sleep(Duration::from_secs_f32(step / speed)).await;
+            remaining -= step;
+        }
+
+        // As fast as possible still lets the other tasks run
+        tokio::task::yield_now().await;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_log_line() {
+        assert_eq!(
+            parse_log_line("1700000000,sbs,MSG,3,1,1,4CA2D6,1,2024/05/01,12:00:00.123"),
+            Some((1700000000000, "sbs".into(), "MSG,3,1,1,4CA2D6,1,2024/05/01,12:00:00.123".into()))
+        );
+        assert_eq!(
+            parse_log_line("1700000001.045,beast,000102030405,80,8D4840D6202CC371C32CE0576098"),
+            Some((1700000001045, "beast".into(), "000102030405,80,8D4840D6202CC371C32CE0576098".into()))
+        );
+        assert_eq!(
+            parse_log_line("{\"time\":1700000002.5,\"source\":\"adsb/sbs\",\"message\":\"MSG,3\\nMSG,4\"}"),
+            Some((1700000002500, "adsb/sbs".into(), "MSG,3\nMSG,4".into()))
+        );
+        // Second line of a multi-line MQTT payload
+        assert_eq!(parse_log_line("MSG,3,1,1,4CA2D6,1,2024/05/01,12:00:00.123"), None);
+        assert_eq!(PayloadFormat::sniff("*8D4840D6202CC371C32CE0576098;"), PayloadFormat::Avr);
+        assert_eq!(PayloadFormat::sniff("{\"hex\":\"3c6586\"}"), PayloadFormat::Json);
+        assert_eq!(PayloadFormat::sniff("MSG,3,1,1,4CA2D6"), PayloadFormat::SbsBatch);
+    }
+
+    #[test]
+    fn test_thin_snapshots() {
+        let mut snapshots: Vec<Snapshot> = (0..5)
+            .map(|index| Snapshot {
+                timestamp: index * SNAPSHOT_INTERVAL_MS,
+                offset: index * 100,
+                plane_db: PlaneDbSnapshot::default(),
+            })
+            .collect();
+        assert_eq!(thin_snapshots(&mut snapshots, SNAPSHOT_INTERVAL_MS), 2 * SNAPSHOT_INTERVAL_MS);
+        let offsets: Vec<u64> = snapshots.iter().map(|snapshot| snapshot.offset).collect();
+        assert_eq!(offsets, [0, 200, 400]);
+    }
+}
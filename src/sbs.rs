@@ -1,58 +1,431 @@
 use chrono::{NaiveDate, NaiveTime}; // For date and time handling
+use serde::Serialize;
+use std::fmt;
 
-#[derive(Debug, Default)]
-struct SbsMessage {
-    message_type: String,        // Message type (e.g., "MSG")
-    transmission_type: u8,       // Transmission type (e.g., 1, 2, 3, etc.)
-    session_id: Option<String>,  // Session ID (optional, often empty)
-    aircraft_id: Option<String>, // Aircraft ID (optional, often empty)
-    hex_ident: String,           // ICAO 24-bit address in hexadecimal, unique identifier
-    flight_id: Option<String>,   // Flight ID (optional, often empty)
-    generated_date: NaiveDate,   // Date the message was generated (UTC)
-    generated_time: NaiveTime,   // Time the message was generated (UTC)
-    logged_date: NaiveDate,      // Date the message was logged (UTC)
-    logged_time: NaiveTime,      // Time the message was logged (UTC)
-    call_sign: Option<String>,   // Aircraft call_sign (optional)
-    altitude: Option<u32>,       // Altitude in feet (optional)
-    ground_speed: Option<u16>,   // Ground speed in knots (optional)
-    track: Option<f32>,          // Track angle in degrees (optional)
-    latitude: Option<f32>,       // Latitude in decimal degrees (optional)
-    longitude: Option<f32>,      // Longitude in decimal degrees (optional)
-    vertical_rate: Option<i16>,  // Vertical rate in feet per minute (optional)
-    squawk: Option<String>,      // Transponder squawk code (optional)
-    alert: Option<bool>,         // Alert flag (true if squawk has changed)
-    emergency: Option<bool>,     // Emergency flag (true if emergency code is set)
-    spi: Option<bool>,           // Special Position Indicator flag
-    is_on_ground: Option<bool>,  // Ground status flag
+// Number of comma separated fields of a BaseStation MSG line
+const SBS_MSG_FIELDS: usize = 22;
+// SEL, ID, AIR, STA and CLK lines share the first 10 fields with MSG lines
+const SBS_HEADER_FIELDS: usize = 10;
+
+/// Any BaseStation record, MSG lines carry the aircraft data
+#[derive(Debug, Clone, PartialEq)]
+pub enum SbsRecord {
+    Msg(SbsMessage),
+    Sel { hex_ident: String, call_sign: Option<String> }, // Selection change
+    Id { hex_ident: String, call_sign: Option<String> },  // New or changed call sign
+    Air { hex_ident: String },                            // New aircraft
+    Sta { hex_ident: String, status: SbsStatus },         // Status change
+    Clk { logged_date: NaiveDate, logged_time: NaiveTime }, // Heartbeat of the source
+}
+
+/// Status of STA records
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SbsStatus {
+    PositionLost, // PL
+    SignalLost,   // SL
+    Removed,      // RM
+    Deleted,      // AD
+    Ok,           // OK
+}
+
+impl SbsStatus {
+    fn parse(field: &str) -> Option<SbsStatus> {
+        match field.trim() {
+            "PL" => Some(SbsStatus::PositionLost),
+            "SL" => Some(SbsStatus::SignalLost),
+            "RM" => Some(SbsStatus::Removed),
+            "AD" => Some(SbsStatus::Deleted),
+            "OK" => Some(SbsStatus::Ok),
+            _ => None,
+        }
+    }
+
+    pub fn description(&self) -> &'static str {
+        match self {
+            SbsStatus::PositionLost => "position lost",
+            SbsStatus::SignalLost => "signal lost",
+            SbsStatus::Removed => "removed",
+            SbsStatus::Deleted => "deleted",
+            SbsStatus::Ok => "ok",
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct SbsMessage {
+    pub message_type: String,        // Message type (e.g., "MSG")
+    pub transmission_type: u8,       // Transmission type (e.g., 1, 2, 3, etc.)
+    pub session_id: Option<String>,  // Session ID (optional, often empty)
+    pub aircraft_id: Option<String>, // Aircraft ID (optional, often empty)
+    pub hex_ident: String,           // ICAO 24-bit address in hexadecimal, unique identifier
+    pub flight_id: Option<String>,   // Flight ID (optional, often empty)
+    pub generated_date: NaiveDate,   // Date the message was generated (UTC)
+    pub generated_time: NaiveTime,   // Time the message was generated (UTC)
+    pub logged_date: NaiveDate,      // Date the message was logged (UTC)
+    pub logged_time: NaiveTime,      // Time the message was logged (UTC)
+    pub call_sign: Option<String>,   // Aircraft call_sign (optional)
+    pub altitude: Option<f32>,       // Altitude in feet (optional)
+    pub ground_speed: Option<f32>,   // Ground speed in knots (optional)
+    pub track: Option<f32>,          // Track angle in degrees (optional)
+    pub latitude: Option<f32>,       // Latitude in decimal degrees (optional)
+    pub longitude: Option<f32>,      // Longitude in decimal degrees (optional)
+    pub vertical_rate: Option<f32>,  // Vertical rate in feet per minute (optional)
+    pub squawk: Option<i32>,         // Transponder squawk code (optional)
+    pub alert: Option<bool>,         // Alert flag (true if squawk has changed)
+    pub emergency: Option<bool>,     // Emergency flag (true if emergency code is set)
+    pub spi: Option<bool>,           // Special Position Indicator flag
+    pub is_on_ground: Option<bool>,  // Ground status flag
+}
+
+/// Reasons why a line is no valid SBS message, one variant per field
+#[derive(Debug, Clone, PartialEq)]
+pub enum SbsParseError {
+    Empty,
+    TooLong(usize),
+    NotAscii,
+    FieldCount(usize),
+    MessageType(String),
+    TransmissionType(String),
+    HexIdent(String),
+    GeneratedDate(String),
+    GeneratedTime(String),
+    LoggedDate(String),
+    LoggedTime(String),
+    Altitude(String),
+    GroundSpeed(String),
+    Track(String),
+    Latitude(String),
+    Longitude(String),
+    VerticalRate(String),
+    Squawk(String),
+    Flag(String),
+    Status(String),
+}
+
+impl SbsParseError {
+    /// Short reason without the offending value, used to group drop statistics
+    pub fn reason(&self) -> &'static str {
+        match self {
+            SbsParseError::Empty => "empty line",
+            SbsParseError::TooLong(_) => "line too long",
+            SbsParseError::NotAscii => "not ASCII",
+            SbsParseError::FieldCount(_) => "wrong number of fields",
+            SbsParseError::MessageType(_) => "message type",
+            SbsParseError::TransmissionType(_) => "transmission type",
+            SbsParseError::HexIdent(_) => "hex ident",
+            SbsParseError::GeneratedDate(_) => "generated date",
+            SbsParseError::GeneratedTime(_) => "generated time",
+            SbsParseError::LoggedDate(_) => "logged date",
+            SbsParseError::LoggedTime(_) => "logged time",
+            SbsParseError::Altitude(_) => "altitude",
+            SbsParseError::GroundSpeed(_) => "ground speed",
+            SbsParseError::Track(_) => "track",
+            SbsParseError::Latitude(_) => "latitude",
+            SbsParseError::Longitude(_) => "longitude",
+            SbsParseError::VerticalRate(_) => "vertical rate",
+            SbsParseError::Squawk(_) => "squawk",
+            SbsParseError::Flag(_) => "flag",
+            SbsParseError::Status(_) => "status",
+        }
+    }
+}
+
+impl fmt::Display for SbsParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SbsParseError::Empty | SbsParseError::NotAscii => write!(f, "{}", self.reason()),
+            SbsParseError::TooLong(len) => write!(f, "{} ({} bytes)", self.reason(), len),
+            SbsParseError::FieldCount(count) => write!(f, "{} ({})", self.reason(), count),
+            SbsParseError::MessageType(value)
+            | SbsParseError::TransmissionType(value)
+            | SbsParseError::HexIdent(value)
+            | SbsParseError::GeneratedDate(value)
+            | SbsParseError::GeneratedTime(value)
+            | SbsParseError::LoggedDate(value)
+            | SbsParseError::LoggedTime(value)
+            | SbsParseError::Altitude(value)
+            | SbsParseError::GroundSpeed(value)
+            | SbsParseError::Track(value)
+            | SbsParseError::Latitude(value)
+            | SbsParseError::Longitude(value)
+            | SbsParseError::VerticalRate(value)
+            | SbsParseError::Squawk(value)
+            | SbsParseError::Flag(value)
+            | SbsParseError::Status(value) => write!(f, "invalid {} '{}'", self.reason(), value),
+        }
+    }
+}
+
+impl std::error::Error for SbsParseError {}
+
+impl SbsRecord {
+    /// Parse any BaseStation line, MSG, SEL, ID, AIR, STA or CLK
+    pub fn parse(line: &str) -> Result<SbsRecord, SbsParseError> {
+        let line = check_line(line)?;
+        let fields: Vec<&str> = line.split(',').collect();
+        if fields[0] == "MSG" {
+            return SbsMessage::parse(line).map(SbsRecord::Msg);
+        }
+        if !["SEL", "ID", "AIR", "STA", "CLK"].contains(&fields[0]) {
+            return Err(SbsParseError::MessageType(fields[0].to_string()));
+        }
+        if fields.len() < SBS_HEADER_FIELDS {
+            return Err(SbsParseError::FieldCount(fields.len()));
+        }
+
+        match fields[0] {
+            "CLK" => Ok(SbsRecord::Clk {
+                logged_date: NaiveDate::parse_from_str(fields[8], "%Y/%m/%d")
+                    .map_err(|_| SbsParseError::LoggedDate(fields[8].to_string()))?,
+                logged_time: NaiveTime::parse_from_str(fields[9], "%H:%M:%S%.f")
+                    .map_err(|_| SbsParseError::LoggedTime(fields[9].to_string()))?,
+            }),
+            "AIR" => Ok(SbsRecord::Air {
+                hex_ident: parse_hex_ident(fields[4])?,
+            }),
+            "SEL" => Ok(SbsRecord::Sel {
+                hex_ident: parse_hex_ident(fields[4])?,
+                call_sign: fields.get(10).and_then(|field| parse_text(field)),
+            }),
+            "ID" => Ok(SbsRecord::Id {
+                hex_ident: parse_hex_ident(fields[4])?,
+                call_sign: fields.get(10).and_then(|field| parse_text(field)),
+            }),
+            _ => {
+                let status = fields.get(10).copied().unwrap_or("");
+                Ok(SbsRecord::Sta {
+                    hex_ident: parse_hex_ident(fields[4])?,
+                    status: SbsStatus::parse(status)
+                        .ok_or_else(|| SbsParseError::Status(status.to_string()))?,
+                })
+            }
+        }
+    }
+}
+
+/// Basic checks of a line before it is split into fields
+fn check_line(line: &str) -> Result<&str, SbsParseError> {
+    let line = line.trim_end_matches(['\r', '\n']);
+    if line.is_empty() {
+        return Err(SbsParseError::Empty);
+    }
+    if line.len() >= 255 {
+        return Err(SbsParseError::TooLong(line.len()));
+    }
+    if !line.is_ascii() {
+        return Err(SbsParseError::NotAscii);
+    }
+    Ok(line)
 }
 
 impl SbsMessage {
-    fn new(
-        message_type: String,
-        transmission_type: u8,
-        session_id: Option<String>,
-        aircraft_id: Option<String>,
-        hex_ident: String,
-        flight_id: Option<String>,
-        generated_date: NaiveDate,
-        generated_time: NaiveTime,
-        logged_date: NaiveDate,
-        logged_time: NaiveTime,
-        call_sign: Option<String>,
-        altitude: Option<u32>,
-        ground_speed: Option<u16>,
-        track: Option<f32>,
-        latitude: Option<f32>,
-        longitude: Option<f32>,
-        vertical_rate: Option<i16>,
-        squawk: Option<String>,
-        alert: Option<bool>,
-        emergency: Option<bool>,
-        spi: Option<bool>,
-        is_on_ground: Option<bool>,
-    ) -> SbsMessage {
-        SbsMessage {
-            ..Default::default()
-        }
-    }
-}
\ No newline at end of file
+    /// Parse one BaseStation line, e.g. "MSG,3,1,1,4CA2D6,1,2024/05/01,12:00:00.000,..."
+    pub fn parse(line: &str) -> Result<SbsMessage, SbsParseError> {
+        let line = check_line(line)?;
+
+        // Split message into (22) pieces by definition of SBS messages
+        let fields: Vec<&str> = line.split(',').collect();
+        if fields[0] != "MSG" {
+            return Err(SbsParseError::MessageType(fields[0].to_string()));
+        }
+        if fields.len() != SBS_MSG_FIELDS {
+            return Err(SbsParseError::FieldCount(fields.len()));
+        }
+
+        let transmission_type = fields[1]
+            .parse::<u8>()
+            .ok()
+            .filter(|tt| (1..=8).contains(tt))
+            .ok_or_else(|| SbsParseError::TransmissionType(fields[1].to_string()))?;
+
+        Ok(SbsMessage {
+            message_type: fields[0].to_string(),
+            transmission_type,
+            session_id: parse_text(fields[2]),
+            aircraft_id: parse_text(fields[3]),
+            hex_ident: parse_hex_ident(fields[4])?,
+            flight_id: parse_text(fields[5]),
+            generated_date: NaiveDate::parse_from_str(fields[6], "%Y/%m/%d")
+                .map_err(|_| SbsParseError::GeneratedDate(fields[6].to_string()))?,
+            generated_time: NaiveTime::parse_from_str(fields[7], "%H:%M:%S%.f")
+                .map_err(|_| SbsParseError::GeneratedTime(fields[7].to_string()))?,
+            logged_date: NaiveDate::parse_from_str(fields[8], "%Y/%m/%d")
+                .map_err(|_| SbsParseError::LoggedDate(fields[8].to_string()))?,
+            logged_time: NaiveTime::parse_from_str(fields[9], "%H:%M:%S%.f")
+                .map_err(|_| SbsParseError::LoggedTime(fields[9].to_string()))?,
+            call_sign: parse_text(fields[10]),
+            altitude: parse_number(fields[11], SbsParseError::Altitude)?,
+            ground_speed: parse_number(fields[12], SbsParseError::GroundSpeed)?,
+            track: parse_number(fields[13], SbsParseError::Track)?,
+            latitude: parse_number(fields[14], SbsParseError::Latitude)?,
+            longitude: parse_number(fields[15], SbsParseError::Longitude)?,
+            vertical_rate: parse_number(fields[16], SbsParseError::VerticalRate)?,
+            squawk: parse_number(fields[17], SbsParseError::Squawk)?,
+            alert: parse_flag(fields[18])?,
+            emergency: parse_flag(fields[19])?,
+            spi: parse_flag(fields[20])?,
+            is_on_ground: parse_flag(fields[21])?,
+        })
+    }
+
+    /// Encode as BaseStation line without line ending, the counterpart of `parse`
+    pub fn to_line(&self) -> String {
+        let text = |field: &Option<String>| field.clone().unwrap_or_default();
+        let number = |field: Option<f32>| field.map(|value| value.to_string()).unwrap_or_default();
+        let flag = |field: Option<bool>| match field {
+            Some(true) => "-1",
+            Some(false) => "0",
+            None => "",
+        };
+        format!(
+            "{},{},{},{},{},{},{},{},{},{},{},{},{},{},{},{},{},{},{},{},{},{}",
+            self.message_type,
+            self.transmission_type,
+            text(&self.session_id),
+            text(&self.aircraft_id),
+            self.hex_ident,
+            text(&self.flight_id),
+            self.generated_date.format("%Y/%m/%d"),
+            self.generated_time.format("%H:%M:%S%.3f"),
+            self.logged_date.format("%Y/%m/%d"),
+            self.logged_time.format("%H:%M:%S%.3f"),
+            text(&self.call_sign),
+            number(self.altitude),
+            number(self.ground_speed),
+            number(self.track),
+            number(self.latitude),
+            number(self.longitude),
+            number(self.vertical_rate),
+            self.squawk.map(|squawk| format!("{:04}", squawk)).unwrap_or_default(),
+            flag(self.alert),
+            flag(self.emergency),
+            flag(self.spi),
+            flag(self.is_on_ground),
+        )
+    }
+}
+
+/// Empty or trimmed text
+fn parse_text(field: &str) -> Option<String> {
+    let field = field.trim();
+    if field.is_empty() {
+        None
+    } else {
+        Some(field.to_string())
+    }
+}
+
+/// 24 bit address as 6 hex digits, '~' in front marks non-ICAO addresses (TIS-B)
+fn parse_hex_ident(field: &str) -> Result<String, SbsParseError> {
+    let hex = field.trim().trim_start_matches('~');
+    if hex.len() == 6 && hex.chars().all(|c| c.is_ascii_hexdigit()) {
+        Ok(hex.to_uppercase())
+    } else {
+        Err(SbsParseError::HexIdent(field.to_string()))
+    }
+}
+
+/// Empty fields are `None`, anything else has to be a number
+fn parse_number<T: std::str::FromStr>(
+    field: &str,
+    error: fn(String) -> SbsParseError,
+) -> Result<Option<T>, SbsParseError> {
+    let field = field.trim();
+    if field.is_empty() {
+        return Ok(None);
+    }
+    field
+        .parse::<T>()
+        .map(Some)
+        .map_err(|_| error(field.to_string()))
+}
+
+/// BaseStation flags are "-1" for true, dump1090 also writes "1"
+fn parse_flag(field: &str) -> Result<Option<bool>, SbsParseError> {
+    match field.trim() {
+        "" => Ok(None),
+        "0" => Ok(Some(false)),
+        "1" | "-1" => Ok(Some(true)),
+        other => Err(SbsParseError::Flag(other.to_string())),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_msg() {
+        let line = "MSG,3,1,1,4CA2D6,1,2024/05/01,12:00:00.123,2024/05/01,12:00:00.150,,36000,,,53.57184,9.98348,,,0,-1,0,0";
+        let message = SbsMessage::parse(line).unwrap();
+        assert_eq!(message.transmission_type, 3);
+        assert_eq!(message.hex_ident, "4CA2D6");
+        assert_eq!(message.call_sign, None);
+        assert_eq!(message.altitude, Some(36000.0));
+        assert_eq!(message.latitude, Some(53.57184));
+        assert_eq!(message.ground_speed, None);
+        assert_eq!(message.alert, Some(false));
+        assert_eq!(message.emergency, Some(true));
+    }
+
+    #[test]
+    fn test_parse_errors() {
+        assert_eq!(SbsMessage::parse(""), Err(SbsParseError::Empty));
+        // Truncated after a reconnect
+        assert_eq!(
+            SbsMessage::parse("MSG,3,1,1,4CA2D6,1,2024/05/01,12:00"),
+            Err(SbsParseError::FieldCount(8))
+        );
+        assert_eq!(
+            SbsMessage::parse("6,1,2024/05/01,12:00:00.123,2024/05/01,12:00:00.150,,36000,,,,,,,,,,,,,,"),
+            Err(SbsParseError::MessageType("6".to_string()))
+        );
+        let line = "MSG,3,1,1,4CA2D6,1,2024/05/01,12:00:00.123,2024/05/01,12:00:00.150,,36O00,,,,,,,,,,";
+        assert_eq!(
+            SbsMessage::parse(line),
+            Err(SbsParseError::Altitude("36O00".to_string()))
+        );
+        let line = "MSG,3,1,1,4CA2,1,2024/05/01,12:00:00.123,2024/05/01,12:00:00.150,,36000,,,,,,,,,,";
+        assert_eq!(
+            SbsMessage::parse(line).unwrap_err().reason(),
+            "hex ident"
+        );
+    }
+
+    #[test]
+    fn test_to_line() {
+        let line = "MSG,3,1,1,4CA2D6,1,2024/05/01,12:00:00.123,2024/05/01,12:00:00.150,RYR4TZ,36000,,,53.57184,9.98348,,0040,0,-1,0,0";
+        let message = SbsMessage::parse(line).unwrap();
+        assert_eq!(message.to_line(), line);
+        assert_eq!(SbsMessage::parse(&message.to_line()), Ok(message));
+    }
+
+    #[test]
+    fn test_parse_records() {
+        let record = SbsRecord::parse("ID,,1,1,4CA2D6,1,2024/05/01,12:00:00.000,2024/05/01,12:00:00.000,RYR4TZ").unwrap();
+        assert_eq!(
+            record,
+            SbsRecord::Id {
+                hex_ident: "4CA2D6".to_string(),
+                call_sign: Some("RYR4TZ".to_string())
+            }
+        );
+        let record = SbsRecord::parse("AIR,,1,1,4CA2D6,1,2024/05/01,12:00:00.000,2024/05/01,12:00:00.000").unwrap();
+        assert_eq!(record, SbsRecord::Air { hex_ident: "4CA2D6".to_string() });
+        let record = SbsRecord::parse("STA,,1,1,4CA2D6,1,2024/05/01,12:00:00.000,2024/05/01,12:00:00.000,PL").unwrap();
+        assert_eq!(
+            record,
+            SbsRecord::Sta {
+                hex_ident: "4CA2D6".to_string(),
+                status: SbsStatus::PositionLost
+            }
+        );
+        let record = SbsRecord::parse("CLK,,,,,,2024/05/01,12:00:00.000,2024/05/01,12:00:00.000").unwrap();
+        assert!(matches!(record, SbsRecord::Clk { .. }));
+        assert_eq!(
+            SbsRecord::parse("STA,,1,1,4CA2D6,1,2024/05/01,12:00:00.000,2024/05/01,12:00:00.000,XX"),
+            Err(SbsParseError::Status("XX".to_string()))
+        );
+    }
+}
@@ -0,0 +1,201 @@
+// Connection health of every data source, shown in the egui "Sources" section.
+//
+// Network tasks report their state through a `SourceMonitor` and wait between reconnects
+// with a `Backoff`, the delay doubles after every failure and is randomized by +-25%.
+
+use chrono::{DateTime, Utc};
+use std::collections::HashMap;
+use std::collections::hash_map::RandomState;
+use std::hash::BuildHasher;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+use once_cell::sync::Lazy;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum SourceState {
+    Connecting,
+    Connected,
+    Failed,
+}
+
+#[derive(Debug, Clone)]
+pub struct SourceHealth {
+    pub kind: &'static str, // "sbs", "beast", "avr", "aircraft.json", "udp" or "mqtt"
+    pub state: SourceState,
+    pub since: DateTime<Utc>, // Time of the last state change
+    pub last_error: Option<(DateTime<Utc>, String)>,
+    pub reconnects: usize,
+    pub messages: usize,
+    pub bytes: usize,
+    pub messages_per_second: f32,
+    pub last_received: Option<DateTime<Utc>>,
+    rate_start: Instant, // Start of the current rate window
+    rate_messages: usize, // Messages in the current rate window
+}
+
+impl SourceHealth {
+    fn new(kind: &'static str) -> SourceHealth {
+        SourceHealth {
+            kind,
+            state: SourceState::Connecting,
+            since: Utc::now(),
+            last_error: None,
+            reconnects: 0,
+            messages: 0,
+            bytes: 0,
+            messages_per_second: 0.0,
+            last_received: None,
+            rate_start: Instant::now(),
+            rate_messages: 0,
+        }
+    }
+
+    /// Messages per second, zero if nothing has been received lately
+    pub fn current_rate(&self) -> f32 {
+        match self.last_received {
+            Some(last_received) if (Utc::now() - last_received).num_seconds() < 5 => {
+                self.messages_per_second
+            }
+            _ => 0.0,
+        }
+    }
+}
+
+// Health of every source by label
+pub static SOURCE_HEALTH: Lazy<Mutex<HashMap<String, SourceHealth>>> =
+    Lazy::new(|| Mutex::new(HashMap::new()));
+
+/// Health of all sources, sorted by label
+pub fn get_source_health() -> Vec<(String, SourceHealth)> {
+    let mut sources: Vec<(String, SourceHealth)> = SOURCE_HEALTH
+        .lock()
+        .map(|sources| {
+            sources
+                .iter()
+                .map(|(label, health)| (label.clone(), health.clone()))
+                .collect()
+        })
+        .unwrap_or_default();
+    sources.sort_by(|a, b| a.0.cmp(&b.0));
+    sources
+}
+
+/// Reports the state of one source to `SOURCE_HEALTH`
+pub struct SourceMonitor {
+    label: String,
+}
+
+impl SourceMonitor {
+    pub fn new(label: &str, kind: &'static str) -> SourceMonitor {
+        if let Ok(mut sources) = SOURCE_HEALTH.lock() {
+            sources.insert(label.to_string(), SourceHealth::new(kind));
+        }
+        SourceMonitor { label: label.to_string() }
+    }
+
+    fn update(&self, update: impl FnOnce(&mut SourceHealth)) {
+        if let Ok(mut sources) = SOURCE_HEALTH.lock()
+            && let Some(health) = sources.get_mut(&self.label)
+        {
+            update(health);
+        }
+    }
+
+    /// Connection attempt, every attempt after a failure counts as reconnect
+    pub fn connecting(&self) {
+        self.update(|health| {
+            if health.state != SourceState::Connecting {
+                health.state = SourceState::Connecting;
+                health.since = Utc::now();
+            }
+            if health.last_error.is_some() {
+                health.reconnects += 1;
+            }
+        });
+    }
+
+    pub fn connected(&self) {
+        self.update(|health| {
+            health.state = SourceState::Connected;
+            health.since = Utc::now();
+        });
+    }
+
+    pub fn failed(&self, error: &str) {
+        eprintln!("{}, error: {}", self.label, error);
+        self.update(|health| {
+            if health.state != SourceState::Failed {
+                health.state = SourceState::Failed;
+                health.since = Utc::now();
+            }
+            health.last_error = Some((Utc::now(), error.to_string()));
+        });
+    }
+
+    pub fn received(&self, messages: usize, bytes: usize) {
+        self.update(|health| {
+            health.messages += messages;
+            health.bytes += bytes;
+            health.last_received = Some(Utc::now());
+            health.rate_messages += messages;
+            let elapsed = health.rate_start.elapsed().as_secs_f32();
+            if elapsed >= 1.0 {
+                health.messages_per_second = health.rate_messages as f32 / elapsed;
+                health.rate_start = Instant::now();
+                health.rate_messages = 0;
+            }
+        });
+    }
+}
+
+/// Exponential backoff with jitter between reconnects
+pub struct Backoff {
+    initial: Duration,
+    max: Duration,
+    attempts: u32,
+}
+
+impl Backoff {
+    pub fn new(initial: Duration, max: Duration) -> Backoff {
+        Backoff { initial, max, attempts: 0 }
+    }
+
+    /// Failures in a row since the last reset
+    pub fn attempts(&self) -> u32 {
+        self.attempts
+    }
+
+    /// Delay before the next attempt, doubles with every call up to `max`
+    pub fn next_delay(&mut self) -> Duration {
+        let delay = self
+            .initial
+            .saturating_mul(1 << self.attempts.min(16))
+            .min(self.max);
+        self.attempts += 1;
+        // Random factor 0.75-1.25, so several sources don't reconnect in lockstep
+        let random = RandomState::new().hash_one(self.attempts) as f64 / u64::MAX as f64;
+        delay.mul_f64(0.75 + random * 0.5)
+    }
+
+    /// Connected, start over with the initial delay
+    pub fn reset(&mut self) {
+        self.attempts = 0;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_backoff() {
+        let mut backoff = Backoff::new(Duration::from_secs(1), Duration::from_secs(10));
+        let delays: Vec<f64> = (0..6).map(|_| backoff.next_delay().as_secs_f64()).collect();
+        for (delay, expected) in delays.iter().zip([1.0, 2.0, 4.0, 8.0, 10.0, 10.0]) {
+            assert!(*delay >= expected * 0.75 && *delay <= expected * 1.25);
+        }
+        assert_eq!(backoff.attempts(), 6);
+        backoff.reset();
+        assert!(backoff.next_delay() <= Duration::from_millis(1250));
+    }
+}
@@ -0,0 +1,231 @@
+// WebSocket stream of aircraft changes, e.g. for wall displays.
+//
+// A client gets a snapshot after connecting and after every filter change, followed by
+// "new", "changed" and "removed" messages. Filters are sent as JSON text messages, e.g.
+// {"hex": ["3C6586"], "bbox": {"lat_min": 53.0, "lon_min": 9.0, "lat_max": 54.0, "lon_max": 11.0}}
+
+use std::collections::HashSet;
+use std::sync::{Arc, Mutex};
+use futures_util::{SinkExt, StreamExt};
+use serde::{Deserialize, Serialize};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::broadcast;
+use tokio_tungstenite::tungstenite::Message;
+
+use crate::data_share::*;
+
+#[derive(Debug, Deserialize, Clone)]
+pub struct WebSocketServer {
+    #[serde(default = "default_ws_bind_address")]
+    pub ws_bind_address: String,
+    pub ws_port: u16,
+}
+
+fn default_ws_bind_address() -> String {
+    "127.0.0.1".to_string()
+}
+
+#[derive(Debug, Deserialize, Clone, Copy, PartialEq)]
+pub struct BoundingBox {
+    pub lat_min: f32,
+    pub lon_min: f32,
+    pub lat_max: f32,
+    pub lon_max: f32,
+}
+
+/// Filter of a client, empty filters match every aircraft
+#[derive(Debug, Deserialize, Clone, Default, PartialEq)]
+pub struct WsFilter {
+    #[serde(default)]
+    pub hex: Vec<String>,
+    pub bbox: Option<BoundingBox>,
+}
+
+impl WsFilter {
+    /// Aircraft without position never match a bounding box
+    pub fn matches(&self, state: &AircraftState) -> bool {
+        if !self.hex.is_empty()
+            && !self
+                .hex
+                .iter()
+                .any(|hex| hex.eq_ignore_ascii_case(&state.hex_ident))
+        {
+            return false;
+        }
+        match (self.bbox, state.latitude, state.longitude) {
+            (None, _, _) => true,
+            (Some(bbox), Some(lat), Some(lon)) => {
+                (bbox.lat_min..=bbox.lat_max).contains(&lat)
+                    && (bbox.lon_min..=bbox.lon_max).contains(&lon)
+            }
+            _ => false,
+        }
+    }
+}
+
+#[derive(Debug, Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum WsMessage {
+    Snapshot { aircraft: Vec<AircraftState> },
+    New { aircraft: AircraftState },
+    Changed { aircraft: AircraftState },
+    Removed { hex_ident: String },
+}
+
+/// Accept WebSocket clients until the listener fails
+pub async fn serve_websocket(
+    data_share: &Arc<Mutex<SharedDataDb>>, ws_server: WebSocketServer,
+) -> Result<(), Box<dyn std::error::Error>> {
+
+    let addr = format!("{}:{}", ws_server.ws_bind_address, ws_server.ws_port);
+    let listener = TcpListener::bind(&addr).await?;
+    println!("Serving WebSocket on ws://{}", addr);
+
+    loop {
+        let (stream, client) = match listener.accept().await {
+            Ok(accepted) => accepted,
+            Err(e) => {
+                eprintln!("Error accepting WebSocket client on {}. Error: {:?}", addr, e);
+                continue;
+            }
+        };
+        let data_share = data_share.clone();
+        tokio::spawn(async move {
+            if let Err(e) = handle_client(&data_share, stream).await {
+                eprintln!("WebSocket client {}, error: {}", client, e);
+            }
+        });
+    }
+}
+
+async fn handle_client(
+    data_share: &Arc<Mutex<SharedDataDb>>, stream: TcpStream,
+) -> Result<(), Box<dyn std::error::Error>> {
+
+    let websocket = tokio_tungstenite::accept_async(stream).await?;
+    let (mut sender, mut receiver) = websocket.split();
+
+    // Subscribe before the snapshot, so no change gets lost in between
+    let mut changes = data_share.lock().unwrap().subscribe_changes();
+    let mut filter = WsFilter::default();
+    let mut sent: HashSet<String> = HashSet::new(); // Aircraft known by the client
+
+    let snapshot = build_snapshot(data_share, &filter, &mut sent);
+    sender.send(Message::text(serde_json::to_string(&snapshot)?)).await?;
+
+    loop {
+        tokio::select! {
+            change = changes.recv() => {
+                let change = match change {
+                    Ok(change) => change,
+                    Err(broadcast::error::RecvError::Lagged(_)) => {
+                        // Client too slow, start over with a snapshot
+                        let snapshot = build_snapshot(data_share, &filter, &mut sent);
+                        sender.send(Message::text(serde_json::to_string(&snapshot)?)).await?;
+                        continue;
+                    }
+                    Err(broadcast::error::RecvError::Closed) => return Ok(()),
+                };
+                let state = data_share
+                    .lock()
+                    .unwrap()
+                    .get_aircraft_state(change.hex_ident().to_string());
+                let hex_ident = change.hex_ident().to_string();
+
+                // Aircraft leaving the filter are removed on the client as well
+                let message = match (change, state) {
+                    (AircraftChange::Removed(_), _) | (_, None) => {
+                        sent.remove(&hex_ident).then_some(WsMessage::Removed { hex_ident })
+                    }
+                    (_, Some(state)) if filter.matches(&state) => {
+                        if sent.insert(hex_ident) {
+                            Some(WsMessage::New { aircraft: state })
+                        } else {
+                            Some(WsMessage::Changed { aircraft: state })
+                        }
+                    }
+                    (_, Some(_)) => {
+                        sent.remove(&hex_ident).then_some(WsMessage::Removed { hex_ident })
+                    }
+                };
+                if let Some(message) = message {
+                    sender.send(Message::text(serde_json::to_string(&message)?)).await?;
+                }
+            }
+            message = receiver.next() => {
+                match message {
+                    Some(Ok(Message::Text(text))) => {
+                        match serde_json::from_str::<WsFilter>(&text) {
+                            Ok(new_filter) => {
+                                filter = new_filter;
+                                let snapshot = build_snapshot(data_share, &filter, &mut sent);
+                                sender.send(Message::text(serde_json::to_string(&snapshot)?)).await?;
+                            }
+                            Err(e) => eprintln!("WebSocket, invalid filter: {}", e),
+                        }
+                    }
+                    Some(Ok(Message::Close(_))) | None => return Ok(()),
+                    Some(Ok(_)) => {} // Ping is answered by tungstenite
+                    Some(Err(e)) => return Err(e.into()),
+                }
+            }
+        }
+    }
+}
+
+/// All aircraft matching the filter, remembers what the client knows
+fn build_snapshot(
+    data_share: &Arc<Mutex<SharedDataDb>>, filter: &WsFilter, sent: &mut HashSet<String>,
+) -> WsMessage {
+    let aircraft: Vec<AircraftState> = data_share
+        .lock()
+        .unwrap()
+        .get_aircraft_states()
+        .into_iter()
+        .filter(|state| filter.matches(state))
+        .collect();
+    sent.clear();
+    sent.extend(aircraft.iter().map(|state| state.hex_ident.clone()));
+    WsMessage::Snapshot { aircraft }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn state(hex_ident: &str, position: Option<(f32, f32)>) -> AircraftState {
+        AircraftState {
+            hex_ident: hex_ident.to_string(),
+            call_sign: None,
+            latitude: position.map(|pos| pos.0),
+            longitude: position.map(|pos| pos.1),
+            altitude: None,
+            ground_speed: None,
+            track: None,
+            vertical_rate: None,
+            squawk: None,
+            is_on_ground: None,
+            position_source: None,
+            last_seen: 0,
+            age_state: AgeState::Active,
+            motion: None,
+            status: None,
+            receivers: 0,
+        }
+    }
+
+    #[test]
+    fn test_filter() {
+        let filter: WsFilter = serde_json::from_str(
+            r#"{"hex": ["3c6586"], "bbox": {"lat_min": 53.0, "lon_min": 9.0, "lat_max": 54.0, "lon_max": 11.0}}"#,
+        )
+        .unwrap();
+        assert!(filter.matches(&state("3C6586", Some((53.5, 10.0)))));
+        assert!(!filter.matches(&state("3C6586", Some((52.5, 10.0)))));
+        assert!(!filter.matches(&state("3C6586", None)));
+        assert!(!filter.matches(&state("4CA2D6", Some((53.5, 10.0)))));
+
+        let filter = WsFilter::default();
+        assert!(filter.matches(&state("4CA2D6", None)));
+    }
+}